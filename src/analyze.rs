@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::error::{stdout_error, Result};
+use crate::plex::{Media, Metadata};
+
+//copies this many times denser than the median are reported as outliers
+const OUTLIER_FACTOR: f64 = 2.5;
+
+#[derive(Serialize, Default, Clone, Copy)]
+pub struct Totals {
+    pub count: u64,
+    pub size_bytes: u64,
+    pub duration_millis: u64,
+}
+
+impl Totals {
+    fn add(&mut self, media: &Media) {
+        self.count += 1;
+        self.size_bytes += media.size();
+        self.duration_millis += media.duration().unwrap_or(0);
+    }
+
+    //average bitrate in kilobits per second
+    pub fn bitrate(&self) -> u64 {
+        (self.size_bytes * 8).checked_div(self.duration_millis).unwrap_or(0)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Outlier {
+    pub title: String,
+    pub year: Option<i32>,
+    pub file: String,
+    pub size_bytes: u64,
+    pub duration_millis: u64,
+    pub mb_per_minute: f64,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub movies: u64,
+    pub totals: Totals,
+    pub resolutions: BTreeMap<String, Totals>,
+    pub video_codecs: BTreeMap<String, Totals>,
+    pub audio_codecs: BTreeMap<String, Totals>,
+    pub containers: BTreeMap<String, Totals>,
+    pub outliers: Vec<Outlier>,
+}
+
+fn mb_per_minute(media: &Media) -> Option<f64> {
+    media.duration()
+        .filter(|&d| d > 0)
+        .map(|d| media.size() as f64 / 1e6 / (d as f64 / 60_000.0))
+}
+
+fn key(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "unknown".into())
+}
+
+pub fn analyze<'a>(library: impl Iterator<Item=&'a Metadata>) -> Report {
+    let mut report = Report {
+        movies: 0,
+        totals: Totals::default(),
+        resolutions: BTreeMap::new(),
+        video_codecs: BTreeMap::new(),
+        audio_codecs: BTreeMap::new(),
+        containers: BTreeMap::new(),
+        outliers: vec![],
+    };
+    let mut densities: Vec<(f64, &Metadata, &Media)> = Vec::new();
+    for metadata in library {
+        report.movies += 1;
+        for media in &metadata.media {
+            report.totals.add(media);
+            report.resolutions.entry(key(&media.video_resolution)).or_default().add(media);
+            report.video_codecs.entry(key(&media.video_codec)).or_default().add(media);
+            report.audio_codecs.entry(key(&media.audio_codec)).or_default().add(media);
+            report.containers.entry(key(&media.container)).or_default().add(media);
+            if let Some(density) = mb_per_minute(media) {
+                densities.push((density, metadata, media));
+            }
+        }
+    }
+
+    densities.sort_by(|a, b| b.0.total_cmp(&a.0));
+    if let Some(&(median, _, _)) = densities.get(densities.len() / 2) {
+        report.outliers = densities.iter()
+            .take_while(|(density, _, _)| *density > median * OUTLIER_FACTOR)
+            .map(|&(density, metadata, media)| Outlier {
+                title: metadata.title.clone(),
+                year: metadata.year,
+                file: media.parts.first().map(|p| p.file.clone()).unwrap_or_default(),
+                size_bytes: media.size(),
+                duration_millis: media.duration().unwrap_or(0),
+                mb_per_minute: density,
+            })
+            .collect();
+    }
+    report
+}
+
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    section: &'a str,
+    name: &'a str,
+    count: u64,
+    size_bytes: u64,
+    duration_millis: u64,
+    bitrate: u64,
+}
+
+impl<'a> CsvRow<'a> {
+    fn new(section: &'a str, name: &'a str, totals: &Totals) -> CsvRow<'a> {
+        CsvRow {
+            section,
+            name,
+            count: totals.count,
+            size_bytes: totals.size_bytes,
+            duration_millis: totals.duration_millis,
+            bitrate: totals.bitrate(),
+        }
+    }
+}
+
+impl Report {
+    fn distributions(&self) -> [(&'static str, &BTreeMap<String, Totals>); 4] {
+        [
+            ("resolution", &self.resolutions),
+            ("video_codec", &self.video_codecs),
+            ("audio_codec", &self.audio_codecs),
+            ("container", &self.containers),
+        ]
+    }
+
+    pub fn print_table(&self) -> Result<()> {
+        self.write_table(&mut io::stdout().lock()).map_err(stdout_error)
+    }
+
+    fn write_table(&self, out: &mut impl Write) -> io::Result<()> {
+        let gb = |bytes: u64| bytes as f64 / 1e9;
+        let hours = |millis: u64| millis as f64 / 3_600_000.0;
+        writeln!(out, "{} movies, {} files, {:.1}GB, {:.1} hours, {} kbps average",
+                 self.movies, self.totals.count, gb(self.totals.size_bytes),
+                 hours(self.totals.duration_millis), self.totals.bitrate())?;
+        for (section, distribution) in self.distributions().iter() {
+            writeln!(out)?;
+            writeln!(out, "{:<16}{:>8}{:>12}{:>10}{:>10}", section, "count", "size (GB)", "hours", "kbps")?;
+            for (name, totals) in distribution.iter() {
+                writeln!(out, "{:<16}{:>8}{:>12.1}{:>10.1}{:>10}",
+                         name, totals.count, gb(totals.size_bytes), hours(totals.duration_millis), totals.bitrate())?;
+            }
+        }
+        if !self.outliers.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "{:<50}{:>10}{:>12}", "outliers", "MB/min", "size (GB)")?;
+            for outlier in &self.outliers {
+                let title = format!("{} ({})", outlier.title,
+                                    outlier.year.map(|y| y.to_string()).unwrap_or_default());
+                writeln!(out, "{:<50}{:>10.1}{:>12.1}", title, outlier.mb_per_minute, gb(outlier.size_bytes))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn print_json(&self) -> Result<()> {
+        let mut out = io::stdout().lock();
+        serde_json::to_writer_pretty(&mut out, self).map_err(stdout_error)?;
+        writeln!(out).map_err(stdout_error)
+    }
+
+    //one row per total, distribution entry and outlier
+    pub fn print_csv(&self) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(io::stdout());
+        writer.serialize(CsvRow::new("total", "all", &self.totals)).map_err(stdout_error)?;
+        for (section, distribution) in self.distributions().iter() {
+            for (name, totals) in distribution.iter() {
+                writer.serialize(CsvRow::new(section, name, totals)).map_err(stdout_error)?;
+            }
+        }
+        for outlier in &self.outliers {
+            writer.serialize(CsvRow::new("outlier", &outlier.title, &Totals {
+                count: 1,
+                size_bytes: outlier.size_bytes,
+                duration_millis: outlier.duration_millis,
+            })).map_err(stdout_error)?;
+        }
+        writer.flush().map_err(stdout_error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plex::Part;
+
+    fn movie(title: &str, resolution: &str, size: u64, minutes: u64) -> Metadata {
+        Metadata {
+            section: "1".into(),
+            guid: String::new(),
+            ids: Default::default(),
+            title: title.into(),
+            plex_key: String::new(),
+            year: Some(2000),
+            duration: Some(minutes * 60_000),
+            media: vec![Media {
+                duration: Some(minutes * 60_000),
+                video_resolution: Some(resolution.into()),
+                video_codec: Some("h264".into()),
+                parts: vec![Part { file: format!("/movies/{}.mkv", title), size, ..Default::default() }],
+                ..Default::default()
+            }],
+            info: Default::default(),
+        }
+    }
+
+    #[test]
+    fn totals_distributions_and_outliers() {
+        let library = [
+            movie("A", "1080", 6_000_000_000, 100),
+            movie("B", "1080", 5_000_000_000, 100),
+            movie("C", "720", 3_000_000_000, 100),
+            movie("D", "4k", 60_000_000_000, 100),
+        ];
+        let report = analyze(library.iter());
+        assert_eq!(report.movies, 4);
+        assert_eq!(report.totals.size_bytes, 74_000_000_000);
+        assert_eq!(report.resolutions["1080"].count, 2);
+        assert_eq!(report.audio_codecs["unknown"].count, 4);
+        assert_eq!(report.outliers.len(), 1);
+        assert_eq!(report.outliers[0].title, "D");
+        assert_eq!(report.outliers[0].mb_per_minute, 600.0);
+    }
+}
@@ -0,0 +1,212 @@
+use std::cell::{Cell, RefCell};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+pub enum Verdict {
+    //go ahead, with the proposal or the title it was edited into
+    Apply(String),
+    Skip,
+    Quit,
+}
+
+enum Answer {
+    Accept,
+    Skip,
+    Edit(String),
+    AcceptAll,
+    Quit,
+}
+
+//"y", "n", "e New Title", "a" or "q", a bare enter accepts
+fn parse_answer(line: &str, editable: bool) -> Option<Answer> {
+    let line = line.trim();
+    let (command, rest) = line.split_once(' ').map(|(c, r)| (c, r.trim())).unwrap_or((line, ""));
+    match command.to_lowercase().as_str() {
+        "" | "y" | "yes" => Some(Answer::Accept),
+        "n" | "no" | "s" | "skip" => Some(Answer::Skip),
+        "e" | "edit" if editable => Some(Answer::Edit(rest.into())),
+        "a" | "all" => Some(Answer::AcceptAll),
+        "q" | "quit" => Some(Answer::Quit),
+        _ => None,
+    }
+}
+
+//asks before each change when validating, and remembers skipped and edited proposals across runs
+pub struct Approvals {
+    connection: Connection,
+    validate: bool,
+    //decisions of test runs are not remembered, they change nothing
+    test: bool,
+    input: RefCell<Box<dyn BufRead>>,
+    accept_all: Cell<bool>,
+    quit: Cell<bool>,
+}
+
+impl Approvals {
+    pub fn open(config: &Config, validate: bool, test: bool) -> Result<Approvals> {
+        Connection::open(Path::new(&config.cache_path))
+            .and_then(|connection| Approvals::with_connection(connection, validate, test,
+                                                              Box::new(BufReader::new(io::stdin()))))
+            .map_err(|why| Error::Config(format!("couldn't open decisions in {}: {}", config.cache_path, why)))
+    }
+
+    fn with_connection(connection: Connection, validate: bool, test: bool,
+                       input: Box<dyn BufRead>) -> rusqlite::Result<Approvals> {
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS decisions (
+                item TEXT NOT NULL,
+                proposal TEXT NOT NULL,
+                decision TEXT NOT NULL,
+                title TEXT NOT NULL,
+                PRIMARY KEY (item, proposal))",
+            params![])?;
+        Ok(Approvals {
+            connection,
+            validate,
+            test,
+            input: RefCell::new(input),
+            accept_all: Cell::new(false),
+            quit: Cell::new(false),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn for_test(validate: bool, input: &str) -> Approvals {
+        Approvals::with_connection(Connection::open_in_memory().unwrap(), validate, false,
+                                   Box::new(io::Cursor::new(input.to_string()))).unwrap()
+    }
+
+    //set once the user quits, nothing is asked or applied after that
+    pub fn quit(&self) -> bool {
+        self.quit.get()
+    }
+
+    fn remembered(&self, item: &str, proposal: &str) -> Option<(String, String)> {
+        self.connection.query_row(
+            "SELECT decision, title FROM decisions WHERE item = ?1 AND proposal = ?2",
+            params![item, proposal],
+            |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()
+            .unwrap_or(None)
+    }
+
+    fn remember(&self, item: &str, proposal: &str, decision: &str, title: &str) {
+        if self.test {
+            return;
+        }
+        if let Err(why) = self.connection.execute(
+            "INSERT OR REPLACE INTO decisions (item, proposal, decision, title) VALUES (?1, ?2, ?3, ?4)",
+            params![item, proposal, decision, title]) {
+            eprintln!("couldn't remember decision: {}", why);
+        }
+    }
+
+    //the title a proposal was edited into on an earlier run
+    pub fn edited(&self, item: &str, proposal: &str) -> Option<String> {
+        self.remembered(item, proposal).filter(|(decision, _)| decision == "edit").map(|(_, title)| title)
+    }
+
+    //proposals skipped on an earlier run are left out of plans
+    pub fn rejected(&self, item: &str, proposal: &str) -> bool {
+        matches!(self.remembered(item, proposal), Some((decision, _)) if decision == "skip")
+    }
+
+    fn read_line(&self, prompt: &str) -> Option<String> {
+        print!("{}", prompt);
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        match self.input.borrow_mut().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim().to_string()),
+        }
+    }
+
+    //item names what is changed, e.g. "rename/1881", proposal what it is changed into
+    pub fn ask(&self, item: &str, proposal: &str, editable: bool) -> Verdict {
+        if self.quit.get() {
+            return Verdict::Quit;
+        }
+        match self.remembered(item, proposal) {
+            Some((decision, _)) if decision == "skip" => {
+                println!("  Skipped, rejected before");
+                return Verdict::Skip;
+            }
+            Some((_, title)) => return Verdict::Apply(title),
+            None => {}
+        }
+        if !self.validate || self.accept_all.get() {
+            return Verdict::Apply(proposal.into());
+        }
+        let choices = if editable { "[y]es, [n]o, [e]dit, [a]ll, [q]uit? " } else { "[y]es, [n]o, [a]ll, [q]uit? " };
+        loop {
+            //end of input quits rather than accepting everything that is left
+            let line = match self.read_line(choices) {
+                Some(line) => line,
+                None => {
+                    self.quit.set(true);
+                    return Verdict::Quit;
+                }
+            };
+            match parse_answer(&line, editable) {
+                Some(Answer::Accept) => return Verdict::Apply(proposal.into()),
+                Some(Answer::Skip) => {
+                    self.remember(item, proposal, "skip", "");
+                    return Verdict::Skip;
+                }
+                Some(Answer::Edit(title)) => {
+                    let title = if title.is_empty() { self.read_line("title: ").unwrap_or_default() } else { title };
+                    if !title.is_empty() {
+                        self.remember(item, proposal, "edit", &title);
+                        //a plan carries the edited title, it is not asked about again
+                        self.remember(item, &title, "accept", &title);
+                        return Verdict::Apply(title);
+                    }
+                }
+                Some(Answer::AcceptAll) => {
+                    self.accept_all.set(true);
+                    return Verdict::Apply(proposal.into());
+                }
+                Some(Answer::Quit) => {
+                    self.quit.set(true);
+                    return Verdict::Quit;
+                }
+                None => println!("Unknown answer {}", line),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn answers_and_remembered_decisions() {
+        let approvals = Approvals::for_test(true, "n\nwhat\ne Eight and a Half\n\na\n");
+        assert!(matches!(approvals.ask("rename/1", "8½", true), Verdict::Skip));
+        assert!(matches!(approvals.ask("rename/2", "8½", true), Verdict::Apply(t) if t == "Eight and a Half"));
+        assert!(matches!(approvals.ask("rename/3", "Fight Club", true), Verdict::Apply(t) if t == "Fight Club"));
+        assert!(matches!(approvals.ask("rename/4", "Fyre", true), Verdict::Apply(_)));
+        //everything after [a]ll is accepted, earlier decisions still stand
+        assert!(matches!(approvals.ask("rename/5", "3:10 to Yuma", true), Verdict::Apply(_)));
+        assert!(matches!(approvals.ask("rename/1", "8½", true), Verdict::Skip));
+        assert_eq!(approvals.edited("rename/2", "8½").as_deref(), Some("Eight and a Half"));
+        assert!(matches!(approvals.ask("rename/2", "Eight and a Half", true), Verdict::Apply(_)));
+        assert!(approvals.rejected("rename/1", "8½"));
+
+        //a test run asks but forgets
+        let testing = Approvals::with_connection(Connection::open_in_memory().unwrap(), true, true,
+                                                 Box::new(io::Cursor::new("n\n".to_string()))).unwrap();
+        assert!(matches!(testing.ask("rename/1", "8½", true), Verdict::Skip));
+        assert!(!testing.rejected("rename/1", "8½"));
+
+        let quitting = Approvals::for_test(true, "q\n");
+        assert!(matches!(quitting.ask("delete/1", "", false), Verdict::Quit));
+        assert!(quitting.quit());
+    }
+}
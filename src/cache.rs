@@ -0,0 +1,180 @@
+use std::fs::create_dir_all;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::config::{CacheTtl, Config};
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy)]
+pub enum Source {
+    Plex,
+    Tmdb,
+    Imdb,
+}
+
+impl Source {
+    fn name(self) -> &'static str {
+        match self {
+            Source::Plex => "plex",
+            Source::Tmdb => "tmdb",
+            Source::Imdb => "imdb",
+        }
+    }
+}
+
+//persistent cache of lookups, each source expires after its own ttl
+pub struct Cache {
+    //shared by the lookup threads
+    connection: Mutex<Connection>,
+    ttl: CacheTtl,
+    offline: bool,
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+impl Cache {
+    pub fn open(config: &Config, offline: bool) -> Result<Cache> {
+        let path = Path::new(&config.cache_path);
+        let cache_error = |why: &dyn std::fmt::Display| {
+            Error::Config(format!("couldn't open cache {}: {}", config.cache_path, why))
+        };
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).map_err(|why| cache_error(&why))?;
+        }
+        let connection = Connection::open(path).map_err(|why| cache_error(&why))?;
+        Cache::with_connection(connection, config.cache_ttl.clone(), offline).map_err(|why| cache_error(&why))
+    }
+
+    fn with_connection(connection: Connection, ttl: CacheTtl, offline: bool) -> rusqlite::Result<Cache> {
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS entries (
+                source TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                updated_at INTEGER NOT NULL,
+                PRIMARY KEY (source, key))",
+            params![])?;
+        Ok(Cache { connection: Mutex::new(connection), ttl, offline })
+    }
+
+    #[cfg(test)]
+    pub fn for_test() -> Cache {
+        Cache::with_connection(Connection::open_in_memory().unwrap(), CacheTtl::default(), false).unwrap()
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    //forgets every entry of the source so the next read fetches it again
+    pub fn clear(&self, source: Source) {
+        if let Err(why) = self.connection().execute("DELETE FROM entries WHERE source = ?1",
+                                                  params![source.name()]) {
+            eprintln!("couldn't clear cache: {}", why);
+        }
+    }
+
+    fn ttl(&self, source: Source) -> i64 {
+        (match source {
+            Source::Plex => self.ttl.plex,
+            Source::Tmdb => self.ttl.tmdb,
+            Source::Imdb => self.ttl.imdb,
+        }) as i64
+    }
+
+    fn get<T: DeserializeOwned>(&self, source: Source, key: &str) -> Option<(T, i64)> {
+        self.connection().query_row(
+            "SELECT value, updated_at FROM entries WHERE source = ?1 AND key = ?2",
+            params![source.name(), key],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))
+            .optional()
+            .unwrap_or(None)
+            .and_then(|(value, updated_at)| serde_json::from_str(&value).ok().map(|v| (v, updated_at)))
+    }
+
+    fn put<T: Serialize>(&self, source: Source, key: &str, value: &T) {
+        if let Ok(value) = serde_json::to_string(value) {
+            if let Err(why) = self.connection().execute(
+                "INSERT OR REPLACE INTO entries (source, key, value, updated_at) VALUES (?1, ?2, ?3, ?4)",
+                params![source.name(), key, value, now()]) {
+                eprintln!("couldn't write cache: {}", why);
+            }
+        }
+    }
+
+    //returns the cached value while it is fresh, otherwise fetches and stores it.
+    //offline only the cache is consulted, and a stale value beats a server that cannot be reached or fails,
+    //but not one saying the thing is gone
+    pub fn read_through<T, F>(&self, source: Source, key: &str, fetch: F) -> Result<T>
+        where T: Serialize + DeserializeOwned,
+              F: FnOnce() -> Result<T> {
+        let cached = self.get::<T>(source, key);
+        if self.offline {
+            return cached.map(|(value, _)| value)
+                .ok_or_else(|| Error::NotFound(format!("{} {} (Offline)", source.name(), key)));
+        }
+        match cached {
+            Some((value, updated_at)) if now() - updated_at < self.ttl(source) => Ok(value),
+            stale => match fetch() {
+                Ok(value) => {
+                    self.put(source, key, &value);
+                    Ok(value)
+                }
+                Err(why @ Error::Transport { .. }) | Err(why @ Error::Status { .. }) =>
+                    stale.map(|(value, _)| value).ok_or(why),
+                Err(why) => Err(why),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    fn cache(tmdb_ttl: u64, offline: bool) -> Cache {
+        Cache::with_connection(Connection::open_in_memory().unwrap(),
+                               CacheTtl { plex: 0, tmdb: tmdb_ttl, imdb: 0 },
+                               offline).unwrap()
+    }
+
+    #[test]
+    fn read_through() {
+        let fetches = Cell::new(0);
+        let fetch = || {
+            fetches.set(fetches.get() + 1);
+            Ok(String::from("Fight Club"))
+        };
+
+        let fresh = cache(60, false);
+        assert_eq!(fresh.read_through(Source::Tmdb, "tt0137523", fetch).unwrap(), "Fight Club");
+        assert_eq!(fresh.read_through(Source::Tmdb, "tt0137523", fetch).unwrap(), "Fight Club");
+        assert_eq!(fetches.get(), 1);
+
+        let expired = cache(0, false);
+        expired.read_through(Source::Tmdb, "tt0137523", fetch).unwrap();
+        expired.read_through(Source::Tmdb, "tt0137523", fetch).unwrap();
+        assert_eq!(fetches.get(), 3);
+        let unreachable = || Err::<String, _>(Error::Transport { url: "tmdb".into(), message: "timed out".into() });
+        assert_eq!(expired.read_through(Source::Tmdb, "tt0137523", unreachable).unwrap(), "Fight Club");
+        let gone = || Err::<String, _>(Error::NotFound("tt0137523".into()));
+        assert!(matches!(expired.read_through(Source::Tmdb, "tt0137523", gone), Err(Error::NotFound(_))));
+
+        let offline = Cache { offline: true, ..expired };
+        assert_eq!(offline.read_through(Source::Tmdb, "tt0137523", fetch).unwrap(), "Fight Club");
+        assert!(matches!(offline.read_through(Source::Tmdb, "tt0056801", fetch), Err(Error::NotFound(_))));
+        assert_eq!(fetches.get(), 3);
+    }
+}
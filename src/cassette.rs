@@ -0,0 +1,149 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+use ureq::Response;
+
+use crate::error::{file_error, Error, Result};
+
+//one request and the response it got, request headers are never kept
+#[derive(Serialize, Deserialize, Clone)]
+struct Interaction {
+    method: String,
+    url: String,
+    body: Option<String>,
+    status: u16,
+    status_text: String,
+    headers: Vec<(String, String)>,
+    response: String,
+}
+
+enum Mode {
+    Record,
+    Replay,
+}
+
+//http interactions saved to a json file while recording, and served from it while replaying
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    //tokens and passwords, replaced before anything is written or matched
+    secrets: Vec<String>,
+    interactions: Mutex<Vec<Interaction>>,
+    //which interactions have been served, a request repeated more often than recorded gets the last answer
+    played: Mutex<Vec<bool>>,
+}
+
+impl Cassette {
+    pub fn record(path: &Path, secrets: Vec<String>) -> Cassette {
+        Cassette {
+            path: path.into(),
+            mode: Mode::Record,
+            secrets: secrets.into_iter().filter(|s| !s.is_empty()).collect(),
+            interactions: Mutex::new(vec![]),
+            played: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn replay(path: &Path, secrets: Vec<String>) -> Result<Cassette> {
+        let file = File::open(path).map_err(|why| file_error(path, format!("couldn't open cassette: {}", why)))?;
+        let interactions: Vec<Interaction> = serde_json::from_reader(BufReader::new(file))
+            .map_err(|why| file_error(path, format!("couldn't read cassette: {}", why)))?;
+        Ok(Cassette {
+            path: path.into(),
+            mode: Mode::Replay,
+            secrets: secrets.into_iter().filter(|s| !s.is_empty()).collect(),
+            played: Mutex::new(vec![false; interactions.len()]),
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    pub fn replaying(&self) -> bool {
+        matches!(self.mode, Mode::Replay)
+    }
+
+    fn redact(&self, text: &str) -> String {
+        self.secrets.iter().fold(text.to_string(), |text, secret| text.replace(secret.as_str(), "<redacted>"))
+    }
+
+    //the recorded response to the request, the earliest one not served yet
+    pub fn play(&self, method: &str, url: &str, body: Option<&str>) -> Result<Response> {
+        let (url, body) = (self.redact(url), body.map(|b| self.redact(b)));
+        let interactions = self.interactions.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut played = self.played.lock().unwrap_or_else(PoisonError::into_inner);
+        let matching: Vec<usize> = interactions.iter().enumerate()
+            .filter(|(_, i)| i.method == method && i.url == url && i.body == body)
+            .map(|(index, _)| index)
+            .collect();
+        let index = matching.iter().find(|&&index| !played[index]).or_else(|| matching.last())
+            .ok_or_else(|| Error::NotFound(format!("{} {} in cassette {}", method, url, self.path.display())))?;
+        played[*index] = true;
+        Ok(to_response(&interactions[*index]))
+    }
+
+    //saves the response and hands back an unread copy of it
+    pub fn save(&self, method: &str, url: &str, body: Option<&str>, response: Response) -> Result<Response> {
+        let headers = response.headers_names().into_iter()
+            .flat_map(|name| response.all(&name).into_iter()
+                .map(|value| (name.clone(), value.to_string()))
+                .collect::<Vec<(String, String)>>())
+            .collect();
+        let (status, status_text) = (response.status(), response.status_text().to_string());
+        let text = response.into_string()
+            .map_err(|why| Error::Transport { url: url.into(), message: why.to_string() })?;
+        let interaction = Interaction {
+            method: method.into(),
+            url: self.redact(url),
+            body: body.map(|b| self.redact(b)),
+            status,
+            status_text,
+            headers,
+            response: self.redact(&text),
+        };
+        let copy = to_response(&Interaction { response: text, ..interaction.clone() });
+
+        let mut interactions = self.interactions.lock().unwrap_or_else(PoisonError::into_inner);
+        interactions.push(interaction);
+        let failed = |why: &dyn std::fmt::Display| file_error(&self.path, format!("couldn't write cassette: {}", why));
+        let json = serde_json::to_string_pretty(&*interactions).map_err(|why| failed(&why))?;
+        fs::write(&self.path, json).map_err(|why| failed(&why))?;
+        Ok(copy)
+    }
+}
+
+fn to_response(interaction: &Interaction) -> Response {
+    let mut raw = format!("HTTP/1.1 {} {}\r\n", interaction.status, interaction.status_text);
+    //the body is stored decoded and whole
+    for (name, value) in interaction.headers.iter()
+        .filter(|(name, _)| !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Transfer-Encoding")) {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw.push_str(&format!("Content-Length: {}\r\n\r\n{}", interaction.response.len(), interaction.response));
+    raw.parse::<Response>().unwrap_or_else(|why| why.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+    use crate::mock::{MockResponse, MockServer};
+    use crate::plex;
+
+    #[test]
+    fn record_then_replay_plex_write() {
+        let path = std::env::temp_dir().join(format!("qable-cassette-{}.json", std::process::id()));
+        let server = MockServer::start(vec![MockResponse::ok("").header("X-Plex-Protocol", "1.0")]);
+        let mut config = Config::for_test(serde_json::json!({"plex_url": server.url, "plex_token": "secret"}));
+        config.cassette = Some(Cassette::record(&path, vec!["secret".into()]));
+        plex::put_plex_movie_metadata(&config, "1", "1881", "Fyre", None).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("secret"));
+
+        //the server is gone, the cassette answers
+        config.cassette = Some(Cassette::replay(&path, vec!["secret".into()]).unwrap());
+        plex::put_plex_movie_metadata(&config, "1", "1881", "Fyre", None).unwrap();
+        assert!(matches!(plex::put_plex_movie_metadata(&config, "1", "1882", "Fyre", None), Err(Error::NotFound(_))));
+        fs::remove_file(&path).unwrap();
+    }
+}
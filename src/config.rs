@@ -1,23 +1,289 @@
+use std::collections::HashMap;
+use std::env;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 
 use serde::Deserialize;
 
+use crate::cassette::Cassette;
+use crate::error::{Error, Result};
+use crate::journal::Journal;
+
 #[derive(Deserialize)]
 pub struct Config {
     pub plex_url: String,
     pub plex_token: String,
+    //library sections to work on by title or type, all movie and show sections when empty
+    #[serde(default)]
+    pub plex_sections: Vec<String>,
+    //library items fetched per request
+    #[serde(default = "default_plex_page_size")]
+    pub plex_page_size: u64,
     pub retries: u8,
     pub api_backoff_millis: u64,
+    //retry policies by service (plex, tmdb, imdb, yts, deluge), replacing retries and api_backoff_millis
+    #[serde(default)]
+    pub retry: HashMap<String, RetryPolicy>,
     pub tmdb_v4_api_key: String,
+    #[serde(default = "default_tmdb_url")]
+    pub tmdb_url: String,
+    //titles and summaries come in this language, certifications from this country
+    #[serde(default = "default_tmdb_language")]
+    pub tmdb_language: String,
+    #[serde(default = "default_tmdb_region")]
+    pub tmdb_region: String,
+    //which tmdb title --clean gives a movie, see TitlePolicy
+    #[serde(default)]
+    pub title_policy: TitlePolicy,
+    //original languages, e.g. "it" or "fr", whose movies keep their original title under the original policy
+    #[serde(default)]
+    pub original_languages: Vec<String>,
+    //parallel tmdb lookups during --clean
+    #[serde(default = "default_tmdb_workers")]
+    pub tmdb_workers: usize,
+    #[serde(default)]
+    pub tmdb_rate_limit: RateLimit,
+    #[serde(default = "default_deluge_url")]
+    pub deluge_url: String,
+    #[serde(default)]
+    pub deluge_password: String,
+    #[serde(default = "default_yts_url")]
+    pub yts_url: String,
+    #[serde(default = "default_torrent_quality")]
+    pub torrent_quality: String,
+    //where --clean --rename-files puts movies, relative to the section folder
+    #[serde(default = "default_naming_template")]
+    pub naming_template: String,
+    //titles less similar to the tmdb title are replaced by --clean, from 0 to 1
+    #[serde(default = "default_title_similarity")]
+    pub title_similarity: f64,
+    //leading articles by language, moved to the end of sort titles
+    #[serde(default = "default_sort_articles")]
+    pub sort_articles: HashMap<String, Vec<String>>,
+    //the articles of titles in an unknown language
+    #[serde(default = "default_sort_language")]
+    pub sort_language: String,
+    #[serde(default = "default_target_size_gb")]
+    pub target_size_gb: f64,
+    #[serde(default)]
+    pub quality: QualityProfile,
+    #[serde(default = "default_cache_path")]
+    pub cache_path: String,
+    #[serde(default)]
+    pub cache_ttl: CacheTtl,
+    //every plex metadata write is appended to it, see --undo
+    #[serde(default = "default_journal_path")]
+    pub journal_path: String,
+    //set by --record and --replay
+    #[serde(skip)]
+    pub cassette: Option<Cassette>,
+    #[serde(skip)]
+    pub journal: Option<Journal>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TitlePolicy {
+    //the title in tmdb_language
+    #[default]
+    Localized,
+    //the original title when the original language is in original_languages, the localized one otherwise
+    Original,
+    //the localized title, with the original title set as originalTitle
+    Both,
+}
+
+//what a movie file should look like, anything else is replaced by --optimize
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct QualityProfile {
+    pub target_gb_per_hour: f64,
+    //files this many times larger than the target are replaced
+    pub size_factor: f64,
+    //allowed video codecs as plex reports them, e.g. h264, hevc
+    pub codecs: Vec<String>,
+    pub min_resolution: String,
+}
+
+impl Default for QualityProfile {
+    fn default() -> Self {
+        QualityProfile {
+            target_gb_per_hour: 4.0,
+            size_factor: 2.0,
+            codecs: vec!["h264".into(), "hevc".into()],
+            min_resolution: "1080".into(),
+        }
+    }
+}
+
+//requests a service accepts, tmdb allows around 50 a second
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimit {
+    pub requests_per_second: f64,
+    //requests sent at once before the rate applies
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            requests_per_second: 40.0,
+            burst: 40,
+        }
+    }
+}
+
+//how often and how patiently failed requests are sent again
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    //attempts in total, including the first
+    pub attempts: u8,
+    //delay after the first failure, doubled after each following one
+    pub base_delay_millis: u64,
+    pub max_delay_millis: u64,
+    //anything else is an answer, not a hiccup
+    pub retry_statuses: Vec<u16>,
+    //posts are sent once unless set, the server may have acted on one that timed out
+    pub retry_posts: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 5,
+            base_delay_millis: 500,
+            max_delay_millis: 30_000,
+            retry_statuses: vec![408, 425, 429, 500, 502, 503, 504],
+            retry_posts: false,
+        }
+    }
+}
+
+//seconds before a cached lookup is fetched again
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct CacheTtl {
+    pub plex: u64,
+    pub tmdb: u64,
+    pub imdb: u64,
+}
+
+impl Default for CacheTtl {
+    fn default() -> Self {
+        CacheTtl {
+            plex: 60 * 60,
+            tmdb: 30 * 24 * 60 * 60,
+            imdb: 24 * 60 * 60,
+        }
+    }
+}
+
+fn default_plex_page_size() -> u64 {
+    500
+}
+
+fn default_tmdb_url() -> String {
+    "https://api.themoviedb.org/3".into()
+}
+
+fn default_tmdb_language() -> String {
+    "en-US".into()
+}
+
+fn default_tmdb_region() -> String {
+    "US".into()
+}
+
+fn default_tmdb_workers() -> usize {
+    8
+}
+
+fn default_deluge_url() -> String {
+    "http://localhost:8112".into()
+}
+
+fn default_yts_url() -> String {
+    "https://yts.mx/api/v2".into()
+}
+
+fn default_torrent_quality() -> String {
+    "1080p".into()
+}
+
+fn default_naming_template() -> String {
+    "{title} ({year})/{title} ({year}) - {resolution}.{ext}".into()
+}
+
+fn default_title_similarity() -> f64 {
+    0.8
+}
+
+fn default_sort_articles() -> HashMap<String, Vec<String>> {
+    let articles: &[(&str, &[&str])] = &[
+        ("en", &["The", "A", "An"]),
+        ("de", &["Der", "Die", "Das", "Ein", "Eine"]),
+        ("fr", &["Le", "La", "Les", "L'", "Un", "Une"]),
+        ("es", &["El", "La", "Los", "Las", "Un", "Una"]),
+        ("it", &["Il", "Lo", "La", "I", "Gli", "Le", "L'", "Un", "Una", "Uno"]),
+    ];
+    articles.iter()
+        .map(|(language, articles)| (language.to_string(), articles.iter().map(|a| a.to_string()).collect()))
+        .collect()
+}
+
+fn default_sort_language() -> String {
+    "en".into()
+}
+
+fn default_target_size_gb() -> f64 {
+    8.0
+}
+
+fn default_cache_path() -> String {
+    env::var("HOME").unwrap_or_else(|_| ".".into()) + "/.qable/cache.sqlite"
+}
+
+fn default_journal_path() -> String {
+    env::var("HOME").unwrap_or_else(|_| ".".into()) + "/.qable/journal.jsonl"
 }
 
 impl Config {
-    pub fn new(config_path: &Path) -> Config {
-        match File::open(&config_path) {
-            Err(why) => panic!("couldn't open config: {}", why),
-            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap(),
+    pub fn new(config_path: &Path) -> Result<Config> {
+        let file = File::open(config_path)
+            .map_err(|why| Error::Config(format!("couldn't open {}: {}", config_path.display(), why)))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|why| Error::Config(format!("couldn't read {}: {}", config_path.display(), why)))
+    }
+
+    //never written to a cassette
+    pub fn secrets(&self) -> Vec<String> {
+        vec![self.plex_token.clone(), self.tmdb_v4_api_key.clone(), self.deluge_password.clone()]
+    }
+
+    pub fn retry_policy(&self, service: &str) -> RetryPolicy {
+        self.retry.get(service).cloned().unwrap_or_else(|| RetryPolicy {
+            attempts: self.retries,
+            base_delay_millis: self.api_backoff_millis,
+            ..Default::default()
+        })
+    }
+
+    //minimal config for tests, fields in overrides replace the defaults
+    #[cfg(test)]
+    pub fn for_test(overrides: serde_json::Value) -> Config {
+        let mut config = serde_json::json!({
+            "plex_url": "",
+            "plex_token": "",
+            "retries": 1,
+            "api_backoff_millis": 0,
+            "tmdb_v4_api_key": ""
+        });
+        for (key, value) in overrides.as_object().unwrap() {
+            config[key] = value.clone();
         }
+        serde_json::from_value(config).unwrap()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use crate::plex::{Media, Metadata, Movies};
+
+pub struct MediaCopy<'a> {
+    pub metadata: &'a Metadata,
+    pub media: &'a Media,
+}
+
+impl MediaCopy<'_> {
+    pub fn describe(&self) -> String {
+        format!("{} ({}) {:.1}GB {}",
+                self.metadata.title,
+                self.metadata.year.map(|y| y.to_string()).unwrap_or_default(),
+                self.media.size() as f64 / 1e9,
+                self.media.parts.first().map(|p| p.file.as_str()).unwrap_or_default())
+    }
+}
+
+pub enum Removal<'a> {
+    //every copy of the library item loses, so the item goes
+    Item(&'a Metadata),
+    Media(MediaCopy<'a>),
+}
+
+pub struct Duplicates<'a> {
+    pub keep: MediaCopy<'a>,
+    pub remove: Vec<MediaCopy<'a>>,
+}
+
+impl<'a> Duplicates<'a> {
+    pub fn removals(&self) -> Vec<Removal<'a>> {
+        let mut removals: Vec<Removal> = Vec::new();
+        for copy in &self.remove {
+            let item_removed = removals.iter().any(|r| match r {
+                Removal::Item(m) => m.plex_key == copy.metadata.plex_key,
+                Removal::Media(_) => false,
+            });
+            if item_removed {
+                continue;
+            }
+            if copy.metadata.plex_key != self.keep.metadata.plex_key {
+                removals.push(Removal::Item(copy.metadata));
+            } else {
+                removals.push(Removal::Media(MediaCopy { metadata: copy.metadata, media: copy.media }));
+            }
+        }
+        removals
+    }
+}
+
+//every movie with more than one copy within a section, the copy closest in size to the target is kept.
+//a movie in several sections, e.g. "Movies" and "Kids", is there on purpose
+pub fn find_duplicates(movies: &Movies, target_bytes: u64) -> Vec<Duplicates<'_>> {
+    let mut keys: Vec<&String> = movies.metadata.keys().collect();
+    keys.sort();
+    keys.into_iter()
+        .flat_map(|key| {
+            let mut sections: BTreeMap<&str, Vec<MediaCopy>> = BTreeMap::new();
+            for metadata in &movies.metadata[key] {
+                for media in &metadata.media {
+                    sections.entry(&metadata.section).or_default().push(MediaCopy { metadata, media });
+                }
+            }
+            sections.into_values()
+        })
+        .filter_map(|mut copies| {
+            if copies.len() < 2 {
+                return None;
+            }
+            copies.sort_by_key(|c| (c.media.size().abs_diff(target_bytes), c.metadata.plex_key.clone(), c.media.id));
+            let keep = copies.remove(0);
+            Some(Duplicates { keep, remove: copies })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plex::{ExternalIds, Part};
+
+    fn metadata(plex_key: &str, sizes: &[(i64, u64)]) -> Metadata {
+        Metadata {
+            section: "1".into(),
+            guid: "com.plexapp.agents.imdb://tt0137523?lang=en".into(),
+            ids: ExternalIds { imdb: Some("tt0137523".into()), ..Default::default() },
+            title: "Fight Club".into(),
+            plex_key: plex_key.into(),
+            year: Some(1999),
+            duration: None,
+            media: sizes.iter().map(|&(id, size)| Media {
+                id,
+                parts: vec![Part { id, file: format!("/movies/{}.mkv", id), size, ..Default::default() }],
+                ..Default::default()
+            }).collect(),
+            info: Default::default(),
+        }
+    }
+
+    #[test]
+    fn keeps_copy_closest_to_target() {
+        let mut movies = Movies { metadata: Default::default(), unmatched: vec![] };
+        movies.metadata.insert("tt0137523".into(), vec![
+            metadata("1", &[(10, 30_000_000_000), (11, 9_000_000_000)]),
+            metadata("2", &[(20, 4_000_000_000)]),
+            //another section keeps its own copy
+            Metadata { section: "2".into(), ..metadata("4", &[(40, 8_000_000_000)]) },
+        ]);
+        movies.metadata.insert("tt0056801".into(), vec![metadata("3", &[(30, 1)])]);
+
+        let duplicates = find_duplicates(&movies, 8_000_000_000);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].keep.media.id, 11);
+
+        let removals = duplicates[0].removals();
+        assert_eq!(removals.len(), 2);
+        assert!(matches!(removals[0], Removal::Item(m) if m.plex_key == "2"));
+        assert!(matches!(&removals[1], Removal::Media(c) if c.media.id == 10));
+    }
+}
@@ -0,0 +1,177 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use ureq::Agent;
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::request::{self, decode, post_agent_response, Client};
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Value,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    message: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Torrent {
+    pub name: String,
+    pub state: String,
+    pub progress: f64,
+}
+
+//client for the deluge web ui json-rpc api, the session cookie is kept by the agent
+pub struct Deluge<'a> {
+    agent: Agent,
+    url: String,
+    next_id: Cell<u64>,
+    client: Client<'a>,
+}
+
+impl Deluge<'_> {
+    pub fn connect(config: &Config) -> Result<Deluge<'_>> {
+        let deluge = Deluge {
+            agent: ureq::agent(),
+            url: format!("{}/json", config.deluge_url.trim_end_matches('/')),
+            next_id: Cell::new(0),
+            client: request::client(config, "deluge"),
+        };
+        if !deluge.call::<bool>("auth.login", json!([config.deluge_password]))? {
+            return Err(Error::Config("Deluge login failed, check deluge_password".into()));
+        }
+        //the web ui can be up without being attached to a daemon
+        if !deluge.call::<bool>("web.connected", json!([]))? {
+            let hosts = deluge.call::<Vec<Vec<Value>>>("web.get_hosts", json!([]))?;
+            let host_id = hosts.first().and_then(|host| host.first()).and_then(|id| id.as_str())
+                .ok_or_else(|| Error::NotFound("Deluge Daemon".into()))?
+                .to_string();
+            deluge.call::<Value>("web.connect", json!([host_id]))?;
+        }
+        Ok(deluge)
+    }
+
+    fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let id = self.next_id.get() + 1;
+        self.next_id.set(id);
+        let response = post_agent_response(
+            &self.agent,
+            &self.url,
+            &[
+                ("Content-Type", "application/json"),
+                ("Accept", "application/json")
+            ],
+            &[],
+            json!({"method": method, "params": params, "id": id}),
+            &self.client)?;
+        let rpc: RpcResponse = decode(Box::new(response))?;
+        //deluge answers a failed call with 200 and an error object, it is not an http failure
+        if let Some(error) = rpc.error {
+            let url = format!("{} {}", self.url, method);
+            return Err(Error::Decode { url, message: format!("deluge error: {}", error.message) });
+        }
+        serde_json::from_value(rpc.result).map_err(|why| Error::decode(&self.url, why))
+    }
+
+    //returns the id (info hash) of the added torrent
+    pub fn add_torrent_magnet(&self, magnet: &str) -> Result<String> {
+        self.call("core.add_torrent_magnet", json!([magnet, {}]))
+    }
+
+    #[cfg_attr(not(test), expect(dead_code, reason = "no command adds .torrent files yet, see the mock-server tests"))]
+    pub fn add_torrent_file(&self, filename: &str, torrent: &[u8]) -> Result<String> {
+        self.call("core.add_torrent_file", json!([filename, base64::encode(torrent), {}]))
+    }
+
+    //torrents keyed by id (info hash)
+    pub fn list_torrents(&self) -> Result<HashMap<String, Torrent>> {
+        self.call("core.get_torrents_status",
+                  json!([{}, ["name", "state", "progress"]]))
+    }
+
+    #[cfg_attr(not(test), expect(dead_code, reason = "no command removes torrents yet, see the mock-server tests"))]
+    pub fn remove_torrent(&self, torrent_id: &str, remove_data: bool) -> Result<bool> {
+        self.call("core.remove_torrent", json!([torrent_id, remove_data]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    fn config(url: &str) -> Config {
+        Config::for_test(json!({"deluge_url": url, "deluge_password": "deluge"}))
+    }
+
+    #[test]
+    fn login_and_add_magnet() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(r#"{"result":true,"error":null,"id":1}"#)
+                .header("Set-Cookie", "_session_id=abc123; Path=/json"),
+            MockResponse::ok(r#"{"result":true,"error":null,"id":2}"#),
+            MockResponse::ok(r#"{"result":"c12fe1c06bba254a9dc9f519b335aa7c1367a88a","error":null,"id":3}"#),
+        ]);
+        let config = config(&server.url);
+        let deluge = Deluge::connect(&config).unwrap();
+        let id = deluge.add_torrent_magnet("magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a");
+        assert_eq!(id.unwrap(), "c12fe1c06bba254a9dc9f519b335aa7c1367a88a");
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/json");
+        assert_eq!(requests[0].json()["method"], "auth.login");
+        assert_eq!(requests[0].json()["params"][0], "deluge");
+        assert_eq!(requests[2].json()["method"], "core.add_torrent_magnet");
+        assert_eq!(requests[2].header("Cookie"), Some("_session_id=abc123"));
+    }
+
+    #[test]
+    fn add_file_and_remove() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(r#"{"result":true,"error":null,"id":1}"#),
+            MockResponse::ok(r#"{"result":true,"error":null,"id":2}"#),
+            MockResponse::ok(r#"{"result":"c12fe1c06bba254a9dc9f519b335aa7c1367a88a","error":null,"id":3}"#),
+            MockResponse::ok(r#"{"result":true,"error":null,"id":4}"#),
+            MockResponse::ok(r#"{"result":null,"error":{"message":"Torrent already being removed","code":4},"id":5}"#),
+        ]);
+        let config = config(&server.url);
+        let deluge = Deluge::connect(&config).unwrap();
+        let id = deluge.add_torrent_file("Fight Club.torrent", b"d8:announce0:e").unwrap();
+        assert_eq!(id, "c12fe1c06bba254a9dc9f519b335aa7c1367a88a");
+        assert!(deluge.remove_torrent(&id, true).unwrap());
+        let failed = deluge.remove_torrent(&id, true).unwrap_err();
+        assert_eq!(failed.exit_code(), 5);
+        assert!(failed.to_string().contains("core.remove_torrent") && failed.to_string().contains("Torrent already being removed"));
+
+        let requests = server.requests();
+        assert_eq!(requests[2].json()["method"], "core.add_torrent_file");
+        assert_eq!(requests[2].json()["params"][0], "Fight Club.torrent");
+        assert_eq!(requests[2].json()["params"][1], "ZDg6YW5ub3VuY2UwOmU=");
+        assert_eq!(requests[3].json()["method"], "core.remove_torrent");
+        assert_eq!(requests[3].json()["params"], json!(["c12fe1c06bba254a9dc9f519b335aa7c1367a88a", true]));
+    }
+
+    #[test]
+    fn connects_to_first_host_and_lists_torrents() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(r#"{"result":true,"error":null,"id":1}"#),
+            MockResponse::ok(r#"{"result":false,"error":null,"id":2}"#),
+            MockResponse::ok(r#"{"result":[["a1b2","127.0.0.1",58846,"localclient"]],"error":null,"id":3}"#),
+            MockResponse::ok(r#"{"result":null,"error":null,"id":4}"#),
+            MockResponse::ok(r#"{"result":{"c12f":{"name":"Fight Club","state":"Seeding","progress":100.0}},"error":null,"id":5}"#),
+        ]);
+        let config = config(&server.url);
+        let deluge = Deluge::connect(&config).unwrap();
+        let torrents = deluge.list_torrents().unwrap();
+        assert_eq!(torrents["c12f"].name, "Fight Club");
+        assert_eq!(server.requests()[3].json()["params"][0], "a1b2");
+    }
+}
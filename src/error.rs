@@ -0,0 +1,69 @@
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum Error {
+    //the config file is missing or malformed
+    Config(String),
+    //the server answered, but not with success
+    //seconds to wait before asking again, sent along with e.g. a 429 or 503
+    Status { url: String, status: u16, message: String, retry_after: Option<u64> },
+    //the server could not be reached or the connection broke
+    Transport { url: String, message: String },
+    //the response was not what we expected
+    Decode { url: String, message: String },
+    //the request worked, there just is no such thing
+    NotFound(String),
+    //a media file could not be moved, or a plan, journal, cassette or stdout could not be read or written
+    File { path: String, message: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn decode(url: &str, why: serde_json::Error) -> Error {
+        //bodies are parsed while they are read, so a broken connection surfaces here too
+        if why.is_io() {
+            Error::Transport { url: url.into(), message: why.to_string() }
+        } else {
+            Error::Decode { url: url.into(), message: why.to_string() }
+        }
+    }
+
+    //process exit code, distinct per kind of error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Status { .. } => 3,
+            Error::Transport { .. } => 4,
+            Error::Decode { .. } => 5,
+            Error::NotFound(_) => 6,
+            Error::File { .. } => 7,
+        }
+    }
+}
+
+//reading or writing one of our own files failed, e.g. a plan or the journal
+pub fn file_error(path: &Path, why: impl fmt::Display) -> Error {
+    Error::File { path: path.display().to_string(), message: why.to_string() }
+}
+
+//writing a report or listing failed, e.g. stdout was piped into head
+pub fn stdout_error(why: impl fmt::Display) -> Error {
+    Error::File { path: "stdout".into(), message: why.to_string() }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "Config Error: {}", message),
+            Error::Status { url, status, message, .. } => write!(f, "{} answered {} {}", url, status, message),
+            Error::Transport { url, message } => write!(f, "{} not reachable: {}", url, message),
+            Error::Decode { url, message } => write!(f, "{} sent an unexpected response: {}", url, message),
+            Error::NotFound(what) => write!(f, "{} Not Found", what),
+            Error::File { path, message } => write!(f, "{}: {}", path, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
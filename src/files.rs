@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::Path;
+
+use crate::plan::FileMove;
+use crate::plex::{Media, Metadata, Section};
+
+//moved along with the movie when they share its name, e.g. Movie.en.srt
+const SUBTITLE_EXTENSIONS: &[&str] = &["srt", "sub", "idx", "ass", "ssa", "vtt", "sup"];
+
+//drops what windows and samba shares refuse in file names
+fn sanitize(value: &str) -> String {
+    value.replace(": ", " - ")
+        .replace(':', "-")
+        .chars()
+        .filter(|c| !"<>\"/\\|?*".contains(*c))
+        .collect::<String>()
+        .trim()
+        .trim_end_matches('.')
+        .to_string()
+}
+
+//plex reports "sd", "480", "720", "1080" and "4k"
+fn resolution(media: &Media) -> String {
+    match media.video_resolution.as_deref().map(str::to_lowercase).as_deref() {
+        Some("4k") => "2160p".into(),
+        Some("sd") | None => "SD".into(),
+        Some(r) if r.ends_with('p') => r.into(),
+        Some(r) => format!("{}p", r),
+    }
+}
+
+//fills {title}, {year}, {resolution}, {codec} and {ext} into the template
+pub fn render(template: &str, fields: &[(&str, String)]) -> String {
+    let path = fields.iter().fold(template.to_string(), |path, (name, value)| {
+        path.replace(&format!("{{{}}}", name), &sanitize(value))
+    });
+    //an unknown year leaves no empty parentheses behind
+    path.replace(" ()", "")
+}
+
+//the location of the section holding the file
+fn library_root<'a>(section: &'a Section, file: &Path) -> Option<&'a Path> {
+    section.locations.iter()
+        .map(|location| Path::new(&location.path))
+        .filter(|root| file.starts_with(root))
+        .max_by_key(|root| root.as_os_str().len())
+}
+
+//subtitles next to the video sharing its name, with the name they get next to the target
+fn sidecars(video: &Path, target: &Path) -> Vec<FileMove> {
+    let (dir, stem, target_stem) = match (video.parent(), video.file_stem(), target.file_stem()) {
+        (Some(dir), Some(stem), Some(target_stem)) => (dir, stem.to_string_lossy(), target_stem.to_string_lossy()),
+        _ => return vec![],
+    };
+    let prefix = format!("{}.", stem);
+    let mut moves: Vec<FileMove> = fs::read_dir(dir).into_iter().flatten().flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let extension = Path::new(&name).extension()?.to_string_lossy().to_lowercase();
+            if !name.starts_with(&prefix) || !SUBTITLE_EXTENSIONS.contains(&extension.as_str()) {
+                return None;
+            }
+            let after = target.with_file_name(format!("{}{}", target_stem, &name[stem.len()..]));
+            Some(FileMove { before: entry.path().to_string_lossy().into(), after: after.to_string_lossy().into() })
+        })
+        .collect();
+    moves.sort_by(|a, b| a.before.cmp(&b.before));
+    moves
+}
+
+//the moves putting the copy where the template says, the video first and its subtitles after.
+//none when it is there already, copies split over several files are left alone
+pub fn plan_moves(template: &str, section: &Section, metadata: &Metadata, media: &Media, title: &str) -> Vec<FileMove> {
+    let part = match media.parts.as_slice() {
+        [part] => part,
+        _ => return vec![],
+    };
+    let file = Path::new(&part.file);
+    let (root, extension) = match (library_root(section, file), file.extension()) {
+        (Some(root), Some(extension)) => (root, extension.to_string_lossy()),
+        _ => return vec![],
+    };
+    let target = root.join(render(template, &[
+        ("title", title.into()),
+        ("year", metadata.year.map(|y| y.to_string()).unwrap_or_default()),
+        ("resolution", resolution(media)),
+        ("codec", media.video_codec.clone().unwrap_or_default()),
+        ("ext", extension.to_string()),
+    ]));
+    if target == file {
+        return vec![];
+    }
+    let mut moves = vec![FileMove { before: part.file.clone(), after: target.to_string_lossy().into() }];
+    moves.extend(sidecars(file, &target));
+    moves
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plex::{ExternalIds, Location, Part};
+
+    #[test]
+    fn moves_with_subtitles() {
+        let root = std::env::temp_dir().join(format!("qable-files-{}", std::process::id()));
+        let folder = root.join("Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT");
+        fs::create_dir_all(&folder).unwrap();
+        let video = folder.join("Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT.mkv");
+        for name in &["Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT.mkv",
+                      "Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT.en.srt",
+                      "Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT.nfo"] {
+            fs::write(folder.join(name), "").unwrap();
+        }
+        let section = Section {
+            key: "1".into(),
+            title: "Movies".into(),
+            kind: "movie".into(),
+            locations: vec![Location { path: root.to_string_lossy().into() }],
+        };
+        let media = Media {
+            id: 1,
+            video_resolution: Some("1080".into()),
+            parts: vec![Part { id: 1, file: video.to_string_lossy().into(), ..Default::default() }],
+            ..Default::default()
+        };
+        let metadata = Metadata {
+            section: "1".into(),
+            guid: "com.plexapp.agents.imdb://tt0056801?lang=en".into(),
+            ids: ExternalIds::default(),
+            title: "Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT".into(),
+            plex_key: "1".into(),
+            year: Some(1963),
+            duration: None,
+            media: vec![media.clone()],
+            info: Default::default(),
+        };
+
+        let moves = plan_moves("{title} ({year})/{title} ({year}) - {resolution}.{ext}", &section, &metadata, &media,
+                               "8½: Otto e mezzo");
+        fs::remove_dir_all(&root).unwrap();
+        let target = root.join("8½ - Otto e mezzo (1963)");
+        assert_eq!(moves, vec![
+            FileMove {
+                before: video.to_string_lossy().into(),
+                after: target.join("8½ - Otto e mezzo (1963) - 1080p.mkv").to_string_lossy().into(),
+            },
+            FileMove {
+                before: folder.join("Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT.en.srt").to_string_lossy().into(),
+                after: target.join("8½ - Otto e mezzo (1963) - 1080p.en.srt").to_string_lossy().into(),
+            },
+        ]);
+    }
+}
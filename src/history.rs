@@ -1,60 +1,520 @@
-use crate::{plex, tmdb};
-use crate::config::Config;
-use std::io::stdin;
+use crate::{analyze, dedupe, files, imdb, optimize, plex, pool, reconcile, sort, title, tmdb, tv, yts};
+use crate::approval::Approvals;
+use crate::cache::{Cache, Source};
+use crate::config::{Config, TitlePolicy};
+use crate::dedupe::Removal;
+use crate::deluge::Deluge;
+use crate::error::{stdout_error, Error, Result};
+use crate::plan::{self, Change, KeptCopy, Plan};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
 
 pub struct MediaManager {
     movies: plex::Movies,
+    shows: Vec<plex::Show>,
+    sections: Vec<plex::Section>,
     config: Config,
+    cache: Cache,
     test: bool,
-    validate: bool,
+    approvals: Approvals,
+    //where changes are planned to instead of being made
+    plan_path: Option<PathBuf>,
 }
 
 impl MediaManager {
-    pub fn new(config: Config, test :bool, validate :bool) -> MediaManager {
-        let pmds = plex::get_plex_library_guids(&config).expect("Exiting (Plex GUIDs Not Found)");
-        MediaManager {
+    pub fn new(config: Config, cache: Cache, sections: &[plex::Section], test :bool, validate :bool,
+               plan_path: Option<PathBuf>) -> Result<MediaManager> {
+        let pmds = plex::get_plex_library_guids(&config, &cache, sections)?;
+        let shows = plex::get_plex_shows(&config, &cache, sections)?;
+        //nothing can be changed without the network
+        let test = test || cache.offline();
+        let approvals = Approvals::open(&config, validate, test)?;
+        Ok(MediaManager {
             config,
+            cache,
             movies: pmds,
+            shows,
+            sections: sections.to_vec(),
             test,
-            validate
+            approvals,
+            plan_path,
+        })
+    }
+
+    //plans the rename when the plex title is dirty, returns the title the item ends up with.
+    //the tmdb title comes with its language when it is the original title, an empty one otherwise
+    fn rename(&self,
+              changes: &mut Vec<Change>,
+              section: &str,
+              kind: &str,
+              plex_key: &str,
+              plex_title: &str,
+              (tmdb_title, language): (&str, &str)) -> String {
+        let reason = title::compare(plex_title, tmdb_title, self.config.title_similarity);
+        if !reason.is_dirty() {
+            return plex_title.into();
+        }
+        //a title edited on an earlier run replaces the tmdb title, one skipped leaves the plex title
+        let item = format!("rename/{}", plex_key);
+        let title = self.approvals.edited(&item, tmdb_title).unwrap_or_else(|| tmdb_title.into());
+        if self.approvals.rejected(&item, &title) {
+            return plex_title.into();
         }
+        if title != plex_title {
+            changes.push(Change::Rename {
+                section: section.into(),
+                kind: kind.into(),
+                plex_key: plex_key.into(),
+                before: plex_title.into(),
+                after: title.clone(),
+                reason: reason.to_string(),
+                language: if title == tmdb_title { language.into() } else { String::new() },
+            });
+        }
+        title
     }
 
-    fn is_dirty(plex_title :&str, tmdb_title : &str) -> bool {
-        let accumlator = |acc, r :char| if r.is_alphanumeric() {acc} else {acc + 1};
-        let plex_special_chars :i8 = plex_title.chars().into_iter().fold(0, accumlator);
-        let tmdb_special_chars :i8 = tmdb_title.chars().into_iter().fold(0, accumlator);
-        (plex_special_chars - tmdb_special_chars).abs() > 3
+    //plans moving each copy of the movie to where naming_template puts it
+    fn move_files(&self, changes: &mut Vec<Change>, metadata: &plex::Metadata, title: &str) {
+        let section = match self.sections.iter().find(|s| s.key == metadata.section) {
+            Some(section) => section,
+            None => return,
+        };
+        for media in &metadata.media {
+            let files = files::plan_moves(&self.config.naming_template, section, metadata, media, title);
+            if !files.is_empty() {
+                changes.push(Change::Move {
+                    section: section.key.clone(),
+                    plex_key: metadata.plex_key.clone(),
+                    media_id: media.id,
+                    title: title.into(),
+                    files,
+                });
+            }
+        }
     }
 
-    pub fn clean_history(&self) {
-        for (imdb_id, plex_metadata) in &self.movies.metadata {
-            if let Some(tmdb_title) = tmdb::get_movie_title(&self.config, &imdb_id) {
-                if MediaManager::is_dirty(&plex_metadata.title, &tmdb_title) {
-                    println!("Renaming {} into {}", plex_metadata.title, tmdb_title);
-                    if self.validate {
-                        let mut input_string = String::new();
-                        stdin().read_line(&mut input_string)
-                            .ok()
-                            .expect("Failed to read line");
-                    }
-                    if !self.test {
-                        plex::put_plex_movie_metadata(&self.config,
-                                                      &plex_metadata.plex_key,
-                                                      &tmdb_title)
+    //writes the changes to the plan file when one is given, makes them otherwise
+    fn finish(&self, command: &str, changes: Vec<Change>) -> Result<()> {
+        let changes: Vec<Change> = changes.into_iter()
+            .filter(|change| {
+                let (item, proposal) = change.approval();
+                !self.approvals.rejected(&item, &proposal)
+            })
+            .collect();
+        let plan = Plan::new(command, changes);
+        match &self.plan_path {
+            Some(path) => {
+                for change in &plan.changes {
+                    println!("{}", change);
+                }
+                plan.save(path)?;
+                println!("Planned {} changes in {}", plan.changes.len(), path.display());
+                Ok(())
+            }
+            None => {
+                let result = plan::apply(&self.config, &self.approvals, &plan, self.test);
+                //the cached library is out of date once anything changed
+                if !self.test {
+                    self.cache.clear(Source::Plex);
+                }
+                result
+            }
+        }
+    }
+
+    //renames titles to their tmdb titles, and the files on disk with them when rename_files is set
+    pub fn clean_history(&self, rename_files: bool) -> Result<()> {
+        //titles are looked up in parallel, renames happen one at a time in title order
+        let mut movies: Vec<&plex::Metadata> = self.movies.metadata.values().flatten().collect();
+        movies.sort_by(|a, b| (&a.title, &a.plex_key).cmp(&(&b.title, &b.plex_key)));
+        let (config, cache) = (&self.config, &self.cache);
+        let tmdb_titles = pool::map(config.tmdb_workers, &movies, |plex_metadata| {
+            tmdb::get_movie_title(config, cache, &plex_metadata.ids)
+        });
+        let mut changes = Vec::new();
+        for (plex_metadata, tmdb_title) in movies.into_iter().zip(tmdb_titles) {
+            if let Some(tmdb_title) = skip_failed(tmdb_title) {
+                let preferred = title::preferred(config, &tmdb_title);
+                let language = if preferred == tmdb_title.original_title { tmdb_title.original_language.as_str() } else { "" };
+                let title = self.rename(&mut changes, &plex_metadata.section, "movie", &plex_metadata.plex_key,
+                                        &plex_metadata.title, (preferred, language));
+                if config.title_policy == TitlePolicy::Both {
+                    keep_original_title(&mut changes, plex_metadata, &tmdb_title);
+                }
+                if rename_files {
+                    self.move_files(&mut changes, plex_metadata, &title);
+                }
+            }
+        }
+        for show in &self.shows {
+            self.clean_show(&mut changes, show);
+        }
+        self.finish("clean", changes)
+    }
+
+    //renames the show and its episodes to their tmdb names
+    fn clean_show(&self, changes: &mut Vec<Change>, show: &plex::Show) {
+        let details = match skip_failed(tmdb::find_tv_id(&self.config, &self.cache, &show.ids)
+            .and_then(|tmdb_id| tmdb::get_tv_details(&self.config, &self.cache, tmdb_id))) {
+            Some(details) => details,
+            None => return,
+        };
+        self.rename(changes, &show.section, "show", &show.plex_key, &show.title, (&details.name, ""));
+        for (season_number, season) in &show.seasons {
+            let tmdb_season = match skip_failed(tmdb::get_tv_season(&self.config, &self.cache, details.id, *season_number)) {
+                Some(tmdb_season) => tmdb_season,
+                None => continue,
+            };
+            for tmdb_episode in &tmdb_season.episodes {
+                //plex and tmdb may number episodes differently (dvd or absolute order), the air date has to agree
+                if let Some(episode) = season.episodes.get(&tmdb_episode.episode_number)
+                    .filter(|episode| tv::same_air_date(episode, tmdb_episode)) {
+                    self.rename(changes, &show.section, "episode", &episode.plex_key, &episode.title,
+                                (&tmdb_episode.name, ""));
+                }
+            }
+        }
+    }
+
+    //sets the selected descriptive fields of every movie to what tmdb says
+    pub fn reconcile(&self, fields: &[String]) -> Result<()> {
+        let mut movies: Vec<&plex::Metadata> = self.movies.metadata.values().flatten().collect();
+        movies.sort_by(|a, b| (&a.title, &a.plex_key).cmp(&(&b.title, &b.plex_key)));
+        let (config, cache) = (&self.config, &self.cache);
+        let details = pool::map(config.tmdb_workers, &movies, |metadata| {
+            tmdb::get_movie_details(config, cache, &metadata.ids)
+        });
+        let mut changes = Vec::new();
+        for (metadata, details) in movies.into_iter().zip(details) {
+            let details = match skip_failed(details) {
+                Some(details) => details,
+                None => continue,
+            };
+            let articles = sort::articles(config, &metadata.title, Some(&details));
+            for difference in reconcile::differences(metadata, &details, fields, &config.tmdb_region, articles) {
+                changes.push(Change::SetField {
+                    section: metadata.section.clone(),
+                    kind: "movie".into(),
+                    plex_key: metadata.plex_key.clone(),
+                    title: metadata.title.clone(),
+                    field: difference.field.into(),
+                    before: difference.plex,
+                    after: difference.tmdb,
+                });
+            }
+        }
+        self.finish("reconcile", changes)
+    }
+
+    //sets the sort title of every movie and show to the title with its leading article moved to the end.
+    //tmdb tells the language of movies keeping their original title
+    pub fn sort_titles(&self) -> Result<()> {
+        let mut movies: Vec<&plex::Metadata> = self.movies.metadata.values().flatten().collect();
+        movies.sort_by(|a, b| (&a.title, &a.plex_key).cmp(&(&b.title, &b.plex_key)));
+        let (config, cache) = (&self.config, &self.cache);
+        let details = pool::map(config.tmdb_workers, &movies, |metadata| {
+            tmdb::get_movie_details(config, cache, &metadata.ids)
+        });
+        let mut changes = Vec::new();
+        for (metadata, details) in movies.into_iter().zip(details) {
+            let articles = sort::articles(config, &metadata.title, skip_failed(details).as_ref());
+            sort_change(&mut changes, &metadata.section, "movie", &metadata.plex_key, &metadata.title,
+                        metadata.info.title_sort.as_deref(), articles);
+        }
+        let mut shows: Vec<&plex::Show> = self.shows.iter().collect();
+        shows.sort_by(|a, b| (&a.title, &a.plex_key).cmp(&(&b.title, &b.plex_key)));
+        for show in shows {
+            let articles = sort::articles(config, &show.title, None);
+            sort_change(&mut changes, &show.section, "show", &show.plex_key, &show.title, show.title_sort.as_deref(), articles);
+        }
+        self.finish("sort", changes)
+    }
+
+    //lists aired episodes of every show that plex does not have
+    pub fn missing_episodes(&self) -> Result<()> {
+        let today = tv::today();
+        let mut shows: Vec<&plex::Show> = self.shows.iter().collect();
+        shows.sort_by(|a, b| a.title.cmp(&b.title));
+        //written rather than printed so a closed pipe ends the report with an error
+        let mut out = io::stdout();
+        for show in shows {
+            let details = match tmdb::find_tv_id(&self.config, &self.cache, &show.ids)
+                .and_then(|tmdb_id| tmdb::get_tv_details(&self.config, &self.cache, tmdb_id)) {
+                Ok(details) => details,
+                Err(Error::NotFound(_)) => {
+                    writeln!(out, "{} not found on TMDb", show.title).map_err(stdout_error)?;
+                    continue;
+                }
+                Err(why) => {
+                    eprintln!("{}: {}", show.title, why);
+                    continue;
+                }
+            };
+            //season 0 holds the specials
+            for season in details.seasons.iter().filter(|s| s.season_number > 0) {
+                let in_plex = show.seasons.get(&season.season_number).map(|s| s.episodes.len()).unwrap_or(0);
+                if in_plex >= season.episode_count as usize {
+                    continue;
+                }
+                if let Some(tmdb_season) = skip_failed(tmdb::get_tv_season(&self.config, &self.cache, details.id, season.season_number)) {
+                    for missing in tv::missing_episodes(show, season.season_number, &tmdb_season, &today) {
+                        writeln!(out, "{} S{:02}E{:02} {} ({})",
+                                 show.title, missing.season, missing.episode, missing.name, missing.air_date)
+                            .map_err(stdout_error)?;
                     }
                 }
             }
         }
+        Ok(())
+    }
+
+    //keeps the copy of each movie closest to the target size and deletes the rest
+    pub fn dedupe(&self) -> Result<()> {
+        let target_bytes = (self.config.target_size_gb * 1e9) as u64;
+        let mut changes = Vec::new();
+        for duplicates in dedupe::find_duplicates(&self.movies, target_bytes) {
+            let keep = KeptCopy {
+                plex_key: duplicates.keep.metadata.plex_key.clone(),
+                media_id: duplicates.keep.media.id,
+                description: duplicates.keep.describe(),
+            };
+            for removal in duplicates.removals() {
+                changes.push(match removal {
+                    Removal::Item(metadata) => Change::DeleteItem {
+                        plex_key: metadata.plex_key.clone(),
+                        title: metadata.title.clone(),
+                        before: metadata.media.iter().flat_map(|m| &m.parts).map(|p| p.file.clone()).collect(),
+                        keep: keep.clone(),
+                    },
+                    Removal::Media(copy) => Change::DeleteMedia {
+                        plex_key: copy.metadata.plex_key.clone(),
+                        media_id: copy.media.id,
+                        title: copy.metadata.title.clone(),
+                        before: copy.media.parts.iter().map(|p| p.file.clone()).collect(),
+                        keep: keep.clone(),
+                    },
+                });
+            }
+        }
+        self.finish("dedupe", changes)
+    }
+
+    //lists library items without an imdb guid along with the likeliest tmdb matches,
+    //and rematches each to its best suggestion when apply is set
+    pub fn unmatched(&self, apply: bool) -> Result<()> {
+        let mut changes = Vec::new();
+        let mut unmatched: Vec<&plex::Metadata> = self.movies.unmatched.iter().collect();
+        unmatched.sort_by(|a, b| a.title.cmp(&b.title));
+        let mut out = io::stdout();
+        for metadata in unmatched {
+            writeln!(out, "{} ({}) {} {}",
+                     metadata.title,
+                     metadata.year.map(|y| y.to_string()).unwrap_or_default(),
+                     metadata.guid,
+                     metadata.media.iter().flat_map(|m| &m.parts).map(|p| p.file.as_str())
+                         .collect::<Vec<&str>>().join(", ")).map_err(stdout_error)?;
+            let suggestions = skip_failed(tmdb::search_movies(&self.config, &self.cache, &metadata.title, metadata.year))
+                .unwrap_or_default();
+            if suggestions.is_empty() {
+                writeln!(out, "  No TMDb match found").map_err(stdout_error)?;
+                continue;
+            }
+            for suggestion in suggestions.iter().take(3) {
+                writeln!(out, "  tmdb:{} {} ({})", suggestion.id, suggestion.title, suggestion.year()).map_err(stdout_error)?;
+            }
+            if !apply {
+                continue;
+            }
+            let best = &suggestions[0];
+            let imdb_id = match skip_failed(tmdb::get_imdb_id(&self.config, &self.cache, best.id)) {
+                Some(imdb_id) => imdb_id,
+                None => {
+                    writeln!(out, "  No IMDb id for {}", best.title).map_err(stdout_error)?;
+                    continue;
+                }
+            };
+            changes.push(Change::Match {
+                plex_key: metadata.plex_key.clone(),
+                title: metadata.title.clone(),
+                before: metadata.guid.clone(),
+                after: imdb_id,
+                name: best.title.clone(),
+            });
+        }
+        if !apply {
+            return Ok(());
+        }
+        self.finish("match", changes)
+    }
+
+    //prints size, duration, bitrate and format statistics of the whole library
+    pub fn analyze(&self, format: &str) -> Result<()> {
+        let report = analyze::analyze(self.movies.metadata.values().flatten().chain(&self.movies.unmatched));
+        match format {
+            "json" => report.print_json(),
+            "csv" => report.print_csv(),
+            _ => report.print_table(),
+        }
+    }
+
+    //the imdb ids of every movie in plex, tmdb knows those of movies plex only has a tmdb id for
+    fn imdb_ids(&self) -> HashSet<String> {
+        let movies: Vec<&plex::Metadata> = self.movies.metadata.values().flatten().collect();
+        let (config, cache) = (&self.config, &self.cache);
+        let resolved = pool::map(config.tmdb_workers, &movies, |metadata| match (&metadata.ids.imdb, &metadata.ids.tmdb) {
+            (Some(imdb_id), _) => Ok(Some(imdb_id.clone())),
+            (None, Some(tmdb_id)) => match tmdb_id.parse() {
+                Ok(tmdb_id) => tmdb::get_imdb_id(config, cache, tmdb_id).map(Some),
+                Err(_) => Ok(None),
+            },
+            (None, None) => Ok(None),
+        });
+        resolved.into_iter().filter_map(|imdb_id| skip_failed(imdb_id).flatten()).collect()
+    }
+
+    //queues every movie on the imdb list that is neither in plex nor in deluge
+    pub fn queue_list(&self, list_id: &str) -> Result<()> {
+        //asked once here so --test and --plan leave out what is downloading, apply asks again before queueing.
+        //offline deluge is out of reach and left to apply
+        let queued = if self.cache.offline() {
+            HashMap::new()
+        } else {
+            Deluge::connect(&self.config)?.list_torrents()?
+        };
+        let in_plex = self.imdb_ids();
+        let mut changes = Vec::new();
+        for imdb_id in imdb::get_imdb_list(&self.config, &self.cache, list_id)? {
+            if in_plex.contains(&imdb_id) {
+                continue;
+            }
+            let torrent = match skip_failed(yts::find_torrents(&self.config, &imdb_id))
+                .and_then(|torrents| torrents.into_iter().next()) {
+                Some(torrent) => torrent,
+                None => {
+                    println!("No torrent found for {}", imdb_id);
+                    continue;
+                }
+            };
+            if let Some(existing) = queued.get(&torrent.hash) {
+                println!("{} ({}) is already queued as {}", torrent.title, torrent.year, existing.name);
+                continue;
+            }
+            changes.push(queue_change(torrent));
+        }
+        self.finish("queue", changes)
+    }
+
+    //plans replacements for movies without a copy satisfying the quality profile,
+    //and queues the replacements unless testing
+    pub fn optimize(&self, format: &str) -> Result<()> {
+        let profile = &self.config.quality;
+        let mut replacements = Vec::new();
+        let mut rows = Vec::new();
+        for replacement in optimize::plan(&self.movies, profile) {
+            let metadata = replacement.metadata;
+            //yts only knows movies by imdb id
+            let torrent = metadata.ids.imdb.as_ref()
+                .and_then(|imdb_id| skip_failed(yts::find_torrents(&self.config, imdb_id)))
+                .and_then(|torrents| optimize::choose_torrent(profile, metadata.duration, torrents));
+            rows.push(optimize::PlanRow {
+                title: metadata.title.clone(),
+                year: metadata.year,
+                imdb_id: metadata.ids.imdb.clone().unwrap_or_default(),
+                violations: replacement.violations.iter().map(|v| v.to_string()).collect::<Vec<String>>().join("; "),
+                replacement: torrent.as_ref()
+                    .map(|t| format!("{} {:.1}GB", t.quality, t.size_bytes as f64 / 1e9))
+                    .unwrap_or_default(),
+            });
+            replacements.extend(torrent);
+        }
+
+        print_replacements(&rows, format)?;
+
+        self.finish("optimize", replacements.into_iter().map(queue_change).collect())
+    }
+}
+
+fn print_replacements(rows: &[optimize::PlanRow], format: &str) -> Result<()> {
+    let mut out = io::stdout().lock();
+    match format {
+        "json" => {
+            serde_json::to_writer_pretty(&mut out, rows).map_err(stdout_error)?;
+            writeln!(out).map_err(stdout_error)
+        }
+        "csv" => {
+            let mut writer = csv::Writer::from_writer(out);
+            for row in rows {
+                writer.serialize(row).map_err(stdout_error)?;
+            }
+            writer.flush().map_err(stdout_error)
+        }
+        _ => rows.iter().try_for_each(|row| {
+            writeln!(out, "{} ({}) {} -> {}",
+                     row.title,
+                     row.year.map(|y| y.to_string()).unwrap_or_default(),
+                     row.violations,
+                     if row.replacement.is_empty() { "no replacement found" } else { &row.replacement })
+                .map_err(stdout_error)
+        }),
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+fn queue_change(torrent: yts::Torrent) -> Change {
+    Change::Queue {
+        magnet: torrent.magnet(),
+        hash: torrent.hash,
+        title: torrent.title,
+        year: torrent.year,
+        quality: torrent.quality,
+        size_bytes: torrent.size_bytes,
+    }
+}
+
+//a failed lookup only skips its item, it is reported unless there simply was nothing to find
+fn skip_failed<T>(result: Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(Error::NotFound(_)) => None,
+        Err(why) => {
+            eprintln!("{}", why);
+            None
+        }
+    }
+}
 
-    #[test]
-    fn is_dirty() {
-        assert!(MediaManager::is_dirty("Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT", "8½"))
+//a titleSort change unless plex sorts the title right already, plex leaves titleSort out while it is the title
+fn sort_change(changes: &mut Vec<Change>, section: &str, kind: &str, plex_key: &str, title: &str,
+               title_sort: Option<&str>, articles: &[String]) {
+    let expected = sort::sort_title(title, articles);
+    if sort::effective_title_sort(title, title_sort.unwrap_or_default()) == expected {
+        return;
     }
-}
\ No newline at end of file
+    changes.push(Change::SetField {
+        section: section.into(),
+        kind: kind.into(),
+        plex_key: plex_key.into(),
+        title: title.into(),
+        field: "titleSort".into(),
+        before: title_sort.unwrap_or_default().into(),
+        after: expected,
+    });
+}
+
+//sets originalTitle to the tmdb original title where it differs from the localized one
+fn keep_original_title(changes: &mut Vec<Change>, metadata: &plex::Metadata, movie: &tmdb::MovieTitle) {
+    let before = metadata.info.original_title.clone().unwrap_or_default();
+    if movie.original_title.is_empty() || movie.original_title == movie.title || movie.original_title == before {
+        return;
+    }
+    changes.push(Change::SetField {
+        section: metadata.section.clone(),
+        kind: "movie".into(),
+        plex_key: metadata.plex_key.clone(),
+        title: metadata.title.clone(),
+        field: "originalTitle".into(),
+        before,
+        after: movie.original_title.clone(),
+    });
+}
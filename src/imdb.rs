@@ -1,21 +1,38 @@
 use serde::Deserialize;
 
+use crate::cache::{Cache, Source};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::request::{self, get_response_data};
+
 #[derive(Deserialize)]
 struct ImdbRow {
-    Position: String,
-    Const: String,
+    #[serde(rename = "Const")]
+    imdb_id: String,
+}
+
+pub fn get_imdb_list(config: &Config, cache: &Cache, list: &str) -> Result<Vec<String>> {
+    cache.read_through(Source::Imdb, &format!("list/{}", list), || fetch_imdb_list(config, list))
+}
+
+fn fetch_imdb_list(config: &Config, list: &str) -> Result<Vec<String>> {
+    let path = format!("https://www.imdb.com/list/{}/export", list);
+    let csv = get_response_data(&path,
+                                &[],
+                                &[("ref_", "ttls_otexp")],
+                                &request::client(config, "imdb"),
+                                |resp| resp.into_string()
+                                    .map_err(|why| Error::Transport { url: path.clone(), message: why.to_string() }))?;
+    //private lists export an empty page
+    Some(parse_imdb_list(&csv)).filter(|list| !list.is_empty())
+        .ok_or_else(|| Error::NotFound(format!("IMDb list {}", list)))
 }
 
-pub fn get_imdb_list(list: &str) -> Vec<String> {
-    let path = format!("https://www.imdb.com/list/{}/export?ref_=ttls_otexp", list);
-    let resp = ureq::get(path.as_str()).call();
-    let csv = resp.into_string().unwrap_or_else(|_| String::new());
+fn parse_imdb_list(csv: &str) -> Vec<String> {
     let mut result: Vec<String> = Vec::new();
     let mut rdr = csv::Reader::from_reader(csv.as_bytes());
-    for line in rdr.deserialize::<ImdbRow>() {
-        if let Ok(imdb_row) = line {
-            result.push(imdb_row.Const);
-        }
+    for imdb_row in rdr.deserialize::<ImdbRow>().flatten() {
+        result.push(imdb_row.imdb_id);
     }
     result
 }
@@ -23,10 +40,24 @@ pub fn get_imdb_list(list: &str) -> Vec<String> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::path::Path;
+
+    use crate::cassette::Cassette;
 
     #[test]
     fn deserialize_imdb_list() {
-        let list = get_imdb_list("ls057163861");
+        let mut config = Config::for_test(serde_json::json!({}));
+        config.cassette = Some(Cassette::replay(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/imdb_list.json")),
+                                                vec![]).unwrap());
+        let list = fetch_imdb_list(&config, "ls057163861").unwrap();
         assert_eq!(list[0], String::from("tt0137523"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_imdb_list_export() {
+        let csv = "Position,Const,Created,Modified,Description,Title,URL,Title Type,IMDb Rating,Runtime (mins),Year,Genres,Num Votes,Release Date,Directors\n\
+                   1,tt0137523,2020-05-01,2020-05-01,,Fight Club,https://www.imdb.com/title/tt0137523/,movie,8.8,139,1999,Drama,1900000,1999-09-10,David Fincher\n\
+                   2,tt0056801,2020-05-01,2020-05-01,,8½,https://www.imdb.com/title/tt0056801/,movie,8.0,138,1963,Drama,110000,1963-02-14,Federico Fellini\n";
+        assert_eq!(parse_imdb_list(csv), vec!["tt0137523", "tt0056801"]);
+    }
+}
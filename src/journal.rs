@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::{file_error, Error, Result};
+use crate::plex::{self, FieldEdit};
+
+//a single metadata field written to plex, with what it was before
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    //every write of one qable invocation shares the run
+    pub run: String,
+    //seconds since the epoch
+    pub timestamp: u64,
+    pub section: String,
+    pub plex_type: String,
+    pub rating_key: String,
+    pub field: String,
+    pub old: String,
+    pub new: String,
+    pub old_locked: bool,
+    pub new_locked: bool,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+//append-only log of plex metadata writes, one json entry per line
+pub struct Journal {
+    path: PathBuf,
+    run: String,
+}
+
+impl Journal {
+    pub fn new(path: &Path) -> Journal {
+        Journal { path: path.into(), run: format!("{}-{}", now(), process::id()) }
+    }
+
+    pub fn run(&self) -> &str {
+        &self.run
+    }
+
+    pub fn append(&self, entries: &[Entry]) -> Result<()> {
+        let failed = |why: &dyn std::fmt::Display| file_error(&self.path, format!("couldn't write journal: {}", why));
+        let mut lines = String::new();
+        for entry in entries {
+            lines += &serde_json::to_string(entry).map_err(|why| failed(&why))?;
+            lines.push('\n');
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).map_err(|why| failed(&why))?;
+        file.write_all(lines.as_bytes()).map_err(|why| failed(&why))
+    }
+}
+
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(why) if why.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(why) => return Err(file_error(path, format!("couldn't open journal: {}", why))),
+    };
+    BufReader::new(file).lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| {
+            line.map_err(|why| why.to_string())
+                .and_then(|line| serde_json::from_str(&line).map_err(|why| why.to_string()))
+                .map_err(|why| file_error(path, format!("couldn't read journal: {}", why)))
+        })
+        .collect()
+}
+
+//the entries of a run, "last" being the latest run, limited to a single item when given
+fn select<'a>(entries: &'a [Entry], run: &str, item: Option<&str>) -> Vec<&'a Entry> {
+    let entries: Vec<&Entry> = entries.iter().filter(|e| item.is_none_or(|key| e.rating_key == key)).collect();
+    let run = match run {
+        "last" => match entries.last() {
+            Some(entry) => entry.run.as_str(),
+            None => return vec![],
+        },
+        run => run,
+    };
+    entries.into_iter().filter(|e| e.run == run).collect()
+}
+
+//puts back the values and lock states the run found, fields changed since are left alone
+pub fn undo(config: &Config, run: &str, item: Option<&str>, test: bool) -> Result<()> {
+    let entries = read(Path::new(&config.journal_path))?;
+    let selected = select(&entries, run, item);
+    if selected.is_empty() {
+        return Err(Error::NotFound(format!("Journal Run {}", run)));
+    }
+    println!("Undoing run {}", selected[0].run);
+    //a field written more than once goes back to what it was before the first write
+    let mut items: BTreeMap<&str, BTreeMap<&str, (&Entry, &Entry)>> = BTreeMap::new();
+    for entry in selected {
+        items.entry(&entry.rating_key).or_default()
+            .entry(&entry.field)
+            .and_modify(|(_, last)| *last = entry)
+            .or_insert((entry, entry));
+    }
+    for (rating_key, fields) in items {
+        let current = match plex::get_plex_item(config, rating_key) {
+            Ok(current) => current,
+            Err(why) => {
+                eprintln!("  {}", why);
+                continue;
+            }
+        };
+        let mut edits = Vec::new();
+        for (field, (first, last)) in fields {
+            if !current.holds(field, &last.new) {
+                println!("  Skipped {} of {}, it is {} now", field, rating_key, current.value(field));
+                continue;
+            }
+            println!("Restoring {} of {} from {} to {}", field, rating_key, last.new, first.old);
+            edits.push((first, FieldEdit { field, value: &first.old, locked: first.old_locked }));
+        }
+        if test || edits.is_empty() {
+            continue;
+        }
+        let entry = edits[0].0;
+        let edits: Vec<FieldEdit> = edits.into_iter().map(|(_, edit)| edit).collect();
+        if let Err(why) = plex::put_plex_fields(config, &entry.section, &entry.plex_type, rating_key, &edits) {
+            eprintln!("  Undo failed ({})", why);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    #[test]
+    fn journal_then_undo() {
+        let path = std::env::temp_dir().join(format!("qable-journal-{}.jsonl", process::id()));
+        let server = MockServer::start(vec![
+            MockResponse::ok(r#"{"MediaContainer":{"Metadata":[{"ratingKey":"1","guid":"local://1","title":"Fyre.2019.1080p"}]}}"#),
+            MockResponse::ok(""),
+            //undo finds the renamed item, with both fields locked
+            //plex leaves out a titleSort that is the title
+            MockResponse::ok(r#"{"MediaContainer":{"Metadata":[{"ratingKey":"1","guid":"local://1","title":"Fyre",
+                "Field":[{"name":"title","locked":true},{"name":"titleSort","locked":true}]}]}}"#),
+            MockResponse::ok(r#"{"MediaContainer":{"Metadata":[{"ratingKey":"1","guid":"local://1","title":"Fyre"}]}}"#),
+            MockResponse::ok(""),
+        ]);
+        let mut config = Config::for_test(serde_json::json!({"plex_url": server.url, "journal_path": path}));
+        config.journal = Some(Journal::new(&path));
+        plex::put_plex_movie_metadata(&config, "1", "1", "Fyre", None).unwrap();
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].field.as_str(), entries[0].old.as_str(), entries[0].old_locked), ("title", "Fyre.2019.1080p", false));
+        assert_eq!((entries[1].field.as_str(), entries[1].old.as_str()), ("titleSort", ""));
+
+        undo(&config, "last", Some("1"), false).unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[4].path.contains("title.value=Fyre.2019.1080p&title.locked=0"));
+        assert!(requests[4].path.contains("titleSort.value=&titleSort.locked=0"));
+        //the undo is journaled too
+        assert_eq!(read(&path).unwrap().len(), 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,43 @@
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 
 use clap::{App, Arg, ArgMatches};
 
-use plex::refresh_plex_library;
+use cache::{Cache, Source};
+use cassette::Cassette;
+use journal::Journal;
+use error::{Error, Result};
+use plan::Plan;
+use plex::{get_plex_sections, refresh_plex_library, select_sections};
 
+mod analyze;
+mod approval;
+mod cache;
+mod cassette;
+mod files;
 mod history;
+mod journal;
+mod title;
 mod tmdb;
+mod release;
 mod request;
 mod config;
+mod error;
+mod plan;
 mod plex;
+mod reconcile;
+mod sort;
+mod pool;
+mod rate_limit;
+mod deluge;
+mod dedupe;
+mod optimize;
+mod imdb;
+mod yts;
+mod tv;
+#[cfg(test)]
+mod mock;
 
 fn matches() -> ArgMatches {
     App::new("qable")
@@ -21,11 +49,83 @@ fn matches() -> ArgMatches {
             .long("clean")
             .takes_value(false)
             .about("clean plex media library"))
+        .arg(Arg::with_name("rename-files")
+            .long("rename-files")
+            .takes_value(false)
+            .requires("clean")
+            .about("also renames movie files and folders on disk, along with their subtitles"))
         .arg(Arg::with_name("refresh")
             .short('r')
             .long("refresh")
             .takes_value(false)
             .about("refresh plex library and movie database"))
+        .arg(Arg::with_name("queue")
+            .short('q')
+            .long("queue")
+            .takes_value(true)
+            .value_name("LIST_ID")
+            .about("queue movies from an imdb list that are missing from plex"))
+        .arg(Arg::with_name("dedupe")
+            .short('d')
+            .long("dedupe")
+            .takes_value(false)
+            .about("delete duplicate movies, keeping the copy closest to the target size"))
+        .arg(Arg::with_name("unmatched")
+            .short('u')
+            .long("unmatched")
+            .takes_value(false)
+            .about("report movies plex could not match, with suggested tmdb matches"))
+        .arg(Arg::with_name("match")
+            .short('m')
+            .long("match")
+            .takes_value(false)
+            .requires("unmatched")
+            .about("match each unmatched movie to its best suggestion"))
+        .arg(Arg::with_name("missing")
+            .long("missing")
+            .takes_value(false)
+            .about("report aired episodes missing from plex show sections"))
+        .arg(Arg::with_name("analyze")
+            .short('a')
+            .long("analyze")
+            .takes_value(false)
+            .about("report size, length, bitrate and format statistics of the plex library"))
+        .arg(Arg::with_name("optimize")
+            .short('o')
+            .long("optimize")
+            .takes_value(false)
+            .about("replace movies that are too large or of an undesired codec or resolution"))
+        .arg(Arg::with_name("reconcile")
+            .long("reconcile")
+            .takes_value(false)
+            .about("sets year, original and sort title, content rating, studio and summary of movies from tmdb"))
+        .arg(Arg::with_name("fields")
+            .long("fields")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .possible_values(reconcile::FIELDS)
+            .requires("reconcile")
+            .about("fields to reconcile, all of them by default"))
+        .arg(Arg::with_name("sort-titles")
+            .long("sort-titles")
+            .takes_value(false)
+            .about("recomputes the sort titles of movies and shows, moving leading articles to the end"))
+        .arg(Arg::with_name("format")
+            .short('f')
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["table", "json", "csv"])
+            .default_value("table")
+            .about("output format of reports"))
+        .arg(Arg::with_name("section")
+            .short('s')
+            .long("section")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME|TYPE")
+            .about("library section to work on, by name or type, may be repeated"))
         .arg(Arg::with_name("test")
             .short('t')
             .long("test")
@@ -35,29 +135,134 @@ fn matches() -> ArgMatches {
             .short('v')
             .long("validate")
             .takes_value(false)
-            .about("requires user input before each change"))
+            .about("asks before each change: accept, skip, edit, accept all or quit"))
+        .arg(Arg::with_name("plan")
+            .long("plan")
+            .takes_value(true)
+            .value_name("FILE")
+            .about("writes the changes a command would make to a plan file instead of making them"))
+        .arg(Arg::with_name("apply")
+            .long("apply")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("plan")
+            .about("makes the changes of a plan file, skipping items that changed since"))
+        .arg(Arg::with_name("undo")
+            .long("undo")
+            .takes_value(true)
+            .value_name("RUN|last")
+            .about("restores the plex metadata a run wrote, as recorded in the journal"))
+        .arg(Arg::with_name("item")
+            .long("item")
+            .takes_value(true)
+            .value_name("RATING_KEY")
+            .requires("undo")
+            .about("undoes a single library item only"))
+        .arg(Arg::with_name("offline")
+            .long("offline")
+            .takes_value(false)
+            .about("answers lookups only from the cache, implies test"))
+        .arg(Arg::with_name("record")
+            .long("record")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("replay")
+            .about("saves every http request and response to a cassette file"))
+        .arg(Arg::with_name("replay")
+            .long("replay")
+            .takes_value(true)
+            .value_name("FILE")
+            .about("answers every http request from a recorded cassette file"))
         .get_matches()
 }
 
 fn main() {
-    let matches = matches();
+    if let Err(why) = run(matches()) {
+        eprintln!("Exiting ({})", why);
+        process::exit(why.exit_code());
+    }
+}
+
+fn run(matches: ArgMatches) -> Result<()> {
     let env = match env::var("QABLE") {
-        Err(_) => env::var("HOME").expect("$HOME not defined") + "/.qable/config.json",
+        Err(_) => env::var("HOME").map_err(|_| Error::Config("$HOME not defined".into()))? + "/.qable/config.json",
         Ok(e) => e,
     };
 
-    let config = config::Config::new(&Path::new(env.as_str()));
+    let mut config = config::Config::new(Path::new(env.as_str()))?;
+    if let Some(path) = matches.value_of("record") {
+        config.cassette = Some(Cassette::record(Path::new(path), config.secrets()));
+    } else if let Some(path) = matches.value_of("replay") {
+        config.cassette = Some(Cassette::replay(Path::new(path), config.secrets())?);
+    }
+    config.journal = Some(Journal::new(Path::new(&config.journal_path)));
+    let cache = Cache::open(&config, matches.is_present("offline"))?;
+
+    let test = matches.is_present("test");
+    let validate = matches.is_present("validate");
+    if let Some(path) = matches.value_of("apply") {
+        let plan = Plan::load(Path::new(path))?;
+        let test = test || cache.offline();
+        let approvals = approval::Approvals::open(&config, validate, test)?;
+        let result = plan::apply(&config, &approvals, &plan, test);
+        if !test {
+            cache.clear(Source::Plex);
+        }
+        return result;
+    }
+    if let Some(run) = matches.value_of("undo") {
+        let test = test || cache.offline();
+        let result = journal::undo(&config, run, matches.value_of("item"), test);
+        if !test {
+            cache.clear(Source::Plex);
+        }
+        return result;
+    }
+    let selectors: Vec<String> = match matches.values_of("section") {
+        Some(values) => values.map(String::from).collect(),
+        None => config.plex_sections.clone(),
+    };
+    let sections = select_sections(&config, get_plex_sections(&config, &cache)?, &selectors);
+    if sections.is_empty() {
+        return Err(Error::NotFound("Selected Plex Sections".into()));
+    }
+
+    if matches.is_present("refresh") {
+        for section in &sections {
+            refresh_plex_library(&config, section)?;
+        }
+        cache.clear(Source::Plex);
+        return Ok(());
+    }
 
     //outputs a list
-    //qualifications for title replacement
-    //has > 2 non-alpha numeric characters not in the tmdb title
+    //qualifications for title replacement, see title::compare
+    //looks like a release name, or is less similar to the tmdb title than title_similarity
+    let plan_path = matches.value_of("plan").map(PathBuf::from);
+    let media_manager = move || history::MediaManager::new(config, cache, &sections, test, validate, plan_path);
     if matches.is_present("clean") {
-        let media_manager = history::MediaManager::new(config,
-                                                       matches.is_present("test"),
-                                                       matches.is_present("validate"));
-        media_manager.clean_history();
-    } else if matches.is_present("refresh") {
-        refresh_plex_library(&config);
+        media_manager()?.clean_history(matches.is_present("rename-files"))?;
+    } else if let Some(list_id) = matches.value_of("queue") {
+        media_manager()?.queue_list(list_id)?;
+    } else if matches.is_present("dedupe") {
+        media_manager()?.dedupe()?;
+    } else if matches.is_present("unmatched") {
+        media_manager()?.unmatched(matches.is_present("match"))?;
+    } else if matches.is_present("reconcile") {
+        let fields: Vec<String> = match matches.values_of("fields") {
+            Some(values) => values.map(String::from).collect(),
+            None => reconcile::FIELDS.iter().map(|f| f.to_string()).collect(),
+        };
+        media_manager()?.reconcile(&fields)?;
+    } else if matches.is_present("sort-titles") {
+        media_manager()?.sort_titles()?;
+    } else if matches.is_present("missing") {
+        media_manager()?.missing_episodes()?;
+    } else if matches.is_present("analyze") {
+        media_manager()?.analyze(matches.value_of("format").unwrap_or("table"))?;
+    } else if matches.is_present("optimize") {
+        media_manager()?.optimize(matches.value_of("format").unwrap_or("table"))?;
     }
+    Ok(())
 }
 
@@ -0,0 +1,163 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::config::QualityProfile;
+use crate::plex::{Media, Metadata, Movies};
+use crate::release::codec_family;
+use crate::yts::Torrent;
+
+pub enum Violation {
+    Oversized(f64),
+    Codec(String),
+    Resolution(String),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Oversized(gb_per_hour) => write!(f, "{:.1}GB/h", gb_per_hour),
+            Violation::Codec(codec) => write!(f, "codec {}", codec),
+            Violation::Resolution(resolution) => write!(f, "resolution {}", resolution),
+        }
+    }
+}
+
+//plex reports "sd", "480", "720", "1080", "2k" and "4k", yts "720p", "1080p" and "2160p"
+pub fn resolution_rank(resolution: &str) -> u32 {
+    match resolution.to_lowercase().trim_end_matches('p') {
+        "sd" => 480,
+        "2k" => 1440,
+        "4k" | "uhd" => 2160,
+        r => r.parse().unwrap_or(0),
+    }
+}
+
+fn gb_per_hour(size_bytes: u64, duration_millis: u64) -> Option<f64> {
+    if duration_millis == 0 {
+        return None;
+    }
+    Some(size_bytes as f64 / 1e9 / (duration_millis as f64 / 3_600_000.0))
+}
+
+pub fn violations(profile: &QualityProfile, media: &Media) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    if let Some(density) = media.duration().and_then(|d| gb_per_hour(media.size(), d)) {
+        if density > profile.target_gb_per_hour * profile.size_factor {
+            violations.push(Violation::Oversized(density));
+        }
+    }
+    if let Some(codec) = &media.video_codec {
+        if !profile.codecs.iter().any(|c| codec_family(c) == codec_family(codec)) {
+            violations.push(Violation::Codec(codec.clone()));
+        }
+    }
+    if let Some(resolution) = &media.video_resolution {
+        if resolution_rank(resolution) < resolution_rank(&profile.min_resolution) {
+            violations.push(Violation::Resolution(resolution.clone()));
+        }
+    }
+    violations
+}
+
+pub struct Replacement<'a> {
+    pub metadata: &'a Metadata,
+    //violations of the copy that comes closest to the profile
+    pub violations: Vec<Violation>,
+}
+
+//movies without a single copy that satisfies the profile
+pub fn plan<'a>(movies: &'a Movies, profile: &QualityProfile) -> Vec<Replacement<'a>> {
+    let mut replacements: Vec<Replacement> = movies.metadata.values()
+        .filter_map(|copies| {
+            copies.iter()
+                .flat_map(|metadata| metadata.media.iter().map(move |media| (metadata, violations(profile, media))))
+                .min_by_key(|(_, violations)| violations.len())
+                .filter(|(_, violations)| !violations.is_empty())
+                .map(|(metadata, violations)| Replacement { metadata, violations })
+        })
+        .collect();
+    replacements.sort_by(|a, b| a.metadata.title.cmp(&b.metadata.title));
+    replacements
+}
+
+//the torrent satisfying the profile that is closest to the target size
+pub fn choose_torrent(profile: &QualityProfile, duration_millis: Option<u64>, torrents: Vec<Torrent>) -> Option<Torrent> {
+    let target = profile.target_gb_per_hour;
+    let density = |t: &Torrent| duration_millis.and_then(|d| gb_per_hour(t.size_bytes, d));
+    torrents.into_iter()
+        .filter(|t| resolution_rank(&t.quality) >= resolution_rank(&profile.min_resolution))
+        .filter(|t| t.video_codec.as_ref()
+            .map(|codec| profile.codecs.iter().any(|c| codec_family(c) == codec_family(codec)))
+            .unwrap_or(true))
+        .filter(|t| density(t).map(|d| d <= target * profile.size_factor).unwrap_or(true))
+        .min_by(|a, b| {
+            let distance = |t: &Torrent| density(t).map(|d| (d - target).abs()).unwrap_or(f64::MAX);
+            distance(a).total_cmp(&distance(b))
+        })
+}
+
+#[derive(Serialize)]
+pub struct PlanRow {
+    pub title: String,
+    pub year: Option<i32>,
+    pub imdb_id: String,
+    pub violations: String,
+    pub replacement: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plex::Part;
+
+    fn profile() -> QualityProfile {
+        QualityProfile {
+            target_gb_per_hour: 4.0,
+            size_factor: 2.0,
+            codecs: vec!["h264".into(), "hevc".into()],
+            min_resolution: "1080".into(),
+        }
+    }
+
+    fn media(codec: &str, resolution: &str, size: u64) -> Media {
+        Media {
+            duration: Some(3_600_000),
+            video_codec: Some(codec.into()),
+            video_resolution: Some(resolution.into()),
+            parts: vec![Part { size, ..Default::default() }],
+            ..Default::default()
+        }
+    }
+
+    fn torrent(quality: &str, codec: &str, size_bytes: u64) -> Torrent {
+        Torrent {
+            title: "Fight Club".into(),
+            year: 1999,
+            quality: quality.into(),
+            video_codec: Some(codec.into()),
+            size_bytes,
+            hash: quality.into(),
+        }
+    }
+
+    #[test]
+    fn profile_violations() {
+        assert!(violations(&profile(), &media("h264", "1080", 4_000_000_000)).is_empty());
+        let found = violations(&profile(), &media("mpeg4", "720", 20_000_000_000));
+        let found: Vec<String> = found.iter().map(|v| v.to_string()).collect();
+        assert_eq!(found, vec!["20.0GB/h", "codec mpeg4", "resolution 720"]);
+    }
+
+    #[test]
+    fn chooses_torrent_closest_to_target() {
+        let torrents = vec![
+            torrent("720p", "x264", 1_000_000_000),
+            torrent("1080p", "x264", 2_000_000_000),
+            torrent("2160p", "x265", 5_000_000_000),
+            torrent("2160p", "x264", 30_000_000_000),
+        ];
+        let chosen = choose_torrent(&profile(), Some(3_600_000), torrents).unwrap();
+        assert_eq!(chosen.size_bytes, 5_000_000_000);
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::approval::{Approvals, Verdict};
+use crate::config::Config;
+use crate::deluge::{self, Deluge};
+use crate::error::{file_error, Error, Result};
+use crate::plex::{self, FieldEdit};
+
+//a single library change, along with the state it expects to find
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Change {
+    //kind is "movie", "show" or "episode", reason tells why the title was flagged.
+    //language is set when after is the tmdb original title, its sort title then uses that language's articles
+    Rename { section: String, kind: String, plex_key: String, before: String, after: String,
+             #[serde(default)] reason: String, #[serde(default)] language: String },
+    //before lists the files going along with the item
+    DeleteItem { plex_key: String, title: String, before: Vec<String>, keep: KeptCopy },
+    DeleteMedia { plex_key: String, media_id: i64, title: String, before: Vec<String>, keep: KeptCopy },
+    //before is the plex guid, after the imdb id
+    Match { plex_key: String, title: String, before: String, after: String, name: String },
+    //expects the torrent not to be in deluge yet
+    Queue { hash: String, title: String, year: i32, quality: String, size_bytes: u64, magnet: String },
+    //a descriptive field of a movie or show, e.g. year or summary, is set and locked
+    SetField { section: String, kind: String, plex_key: String, title: String,
+               field: String, before: String, after: String },
+    //the video first, then its subtitles
+    Move { section: String, plex_key: String, media_id: i64, title: String, files: Vec<FileMove> },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileMove {
+    pub before: String,
+    pub after: String,
+}
+
+//the copy a delete leaves in place, the delete is skipped once it is gone
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeptCopy {
+    pub plex_key: String,
+    pub media_id: i64,
+    //title, year, size and file
+    pub description: String,
+}
+
+impl fmt::Display for KeptCopy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description)
+    }
+}
+
+impl Change {
+    //what is asked about when validating, e.g. ("rename/1881", "Fyre")
+    pub fn approval(&self) -> (String, String) {
+        match self {
+            Change::Rename { plex_key, after, .. } => (format!("rename/{}", plex_key), after.clone()),
+            Change::DeleteItem { plex_key, before, .. } => (format!("delete/{}", plex_key), before.join(", ")),
+            Change::DeleteMedia { plex_key, media_id, before, .. } =>
+                (format!("delete/{}/{}", plex_key, media_id), before.join(", ")),
+            Change::Match { plex_key, after, .. } => (format!("match/{}", plex_key), after.clone()),
+            Change::Queue { hash, title, .. } => (format!("queue/{}", hash), title.clone()),
+            Change::SetField { plex_key, field, after, .. } => (format!("set/{}/{}", plex_key, field), after.clone()),
+            Change::Move { plex_key, media_id, files, .. } => (format!("move/{}/{}", plex_key, media_id),
+                                                               files.first().map(|f| f.after.clone()).unwrap_or_default()),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Rename { before, after, reason, .. } if !reason.is_empty() =>
+                write!(f, "Renaming {} into {} ({})", before, after, reason),
+            Change::Rename { before, after, .. } => write!(f, "Renaming {} into {}", before, after),
+            Change::DeleteItem { plex_key, title, keep, .. } =>
+                write!(f, "Deleting {} ({}) with all its files, keeping {}", title, plex_key, keep),
+            Change::DeleteMedia { title, before, keep, .. } =>
+                write!(f, "Deleting {} {}, keeping {}", title, before.join(", "), keep),
+            Change::Match { title, after, name, .. } => write!(f, "Matching {} to {} ({})", title, name, after),
+            Change::Queue { title, year, quality, size_bytes, .. } =>
+                write!(f, "Queueing {} ({}) {} {:.1}GB", title, year, quality, *size_bytes as f64 / 1e9),
+            Change::SetField { title, field, before, after, .. } =>
+                write!(f, "Setting {} of {} from {} to {}", field, title, shorten(before), shorten(after)),
+            Change::Move { files, .. } => match files.split_first() {
+                Some((video, [])) => write!(f, "Moving {} to {}", video.before, video.after),
+                Some((video, sidecars)) => write!(f, "Moving {} to {} with {} subtitles",
+                                                  video.before, video.after, sidecars.len()),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+//long values like summaries are cut for display
+fn shorten(value: &str) -> String {
+    match value.char_indices().nth(60) {
+        Some((index, _)) => format!("\"{}...\"", &value[..index]),
+        None if value.is_empty() => "nothing".into(),
+        None => format!("\"{}\"", value),
+    }
+}
+
+//the changes a command intends to make, written by --plan and executed by --apply
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Plan {
+    pub command: String,
+    //seconds since the epoch
+    pub created: u64,
+    pub changes: Vec<Change>,
+}
+
+impl Plan {
+    pub fn new(command: &str, changes: Vec<Change>) -> Plan {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Plan { command: command.into(), created, changes }
+    }
+
+    pub fn load(path: &Path) -> Result<Plan> {
+        let file = File::open(path).map_err(|why| file_error(path, format!("couldn't open plan: {}", why)))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|why| file_error(path, format!("couldn't read plan: {}", why)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let failed = |why: &dyn std::fmt::Display| file_error(path, format!("couldn't write plan: {}", why));
+        let json = serde_json::to_string_pretty(self).map_err(|why| failed(&why))?;
+        fs::write(path, json).map_err(|why| failed(&why))
+    }
+}
+
+//deluge is only connected to once the plan queues something
+struct Queue<'a> {
+    deluge: Deluge<'a>,
+    queued: HashMap<String, deluge::Torrent>,
+}
+
+//makes every change that is approved and still finds the library as planned,
+//a failed change is reported and the rest go ahead, the first failure is returned in the end
+pub fn apply(config: &Config, approvals: &Approvals, plan: &Plan, test: bool) -> Result<()> {
+    let mut queue: Option<Queue> = None;
+    let mut failures: Vec<Error> = Vec::new();
+    //the title each renamed item has by now, files are only moved to the title the item got
+    let mut titles: HashMap<&str, String> = HashMap::new();
+    for change in &plan.changes {
+        if approvals.quit() {
+            break;
+        }
+        if let Change::Move { plex_key, title, .. } = change {
+            if let Some(actual) = titles.get(plex_key.as_str()).filter(|actual| *actual != title) {
+                println!("{}\n  Skipped, the title is {} and not {}", change, actual, title);
+                continue;
+            }
+        }
+        if let Change::Rename { plex_key, before, .. } = change {
+            titles.insert(plex_key, before.clone());
+        }
+        println!("{}", change);
+        let (item, proposal) = change.approval();
+        let editable = matches!(change, Change::Rename { .. } | Change::SetField { .. });
+        let value = match approvals.ask(&item, &proposal, editable) {
+            Verdict::Apply(value) => value,
+            Verdict::Skip => continue,
+            Verdict::Quit => break,
+        };
+        if test {
+            if let Change::Rename { plex_key, .. } = change {
+                titles.insert(plex_key, value);
+            }
+            continue;
+        }
+        if let (Change::Queue { .. }, None) = (change, &queue) {
+            let deluge = Deluge::connect(config)?;
+            let queued = deluge.list_torrents()?;
+            queue = Some(Queue { deluge, queued });
+        }
+        let result = changed(config, queue.as_ref(), change)
+            .and_then(|changed| match changed {
+                Some(reason) => {
+                    println!("  Skipped, {}", reason);
+                    Ok(false)
+                }
+                None => make(config, queue.as_ref(), change, &value).map(|_| true),
+            });
+        match (change, result) {
+            (Change::Rename { plex_key, .. }, Ok(true)) => {
+                titles.insert(plex_key, value);
+            }
+            (_, Ok(_)) => {}
+            (_, Err(why)) => {
+                eprintln!("  Failed ({})", why);
+                failures.push(why);
+            }
+        }
+    }
+    if failures.len() > 1 {
+        eprintln!("{} changes failed", failures.len());
+    }
+    match failures.into_iter().next() {
+        Some(why) => Err(why),
+        None => Ok(()),
+    }
+}
+
+//how the item differs from the state the change expects, if it does
+fn changed(config: &Config, queue: Option<&Queue>, change: &Change) -> Result<Option<String>> {
+    let item = |plex_key: &str| match plex::get_plex_item(config, plex_key) {
+        Ok(item) => Ok(Some(item)),
+        Err(Error::NotFound(_)) => Ok(None),
+        Err(why) => Err(why),
+    };
+    let kept = |keep: &KeptCopy| -> Result<Option<String>> {
+        Ok(match item(&keep.plex_key)? {
+            Some(item) if item.media_ids().contains(&keep.media_id) => None,
+            _ => Some(format!("the copy to keep is gone ({})", keep)),
+        })
+    };
+    Ok(match change {
+        Change::Queue { hash, .. } => queue.and_then(|q| q.queued.get(hash))
+            .map(|t| format!("already queued {} ({} {:.0}%)", t.name, t.state, t.progress)),
+        Change::Rename { plex_key, before, .. } => match item(plex_key)? {
+            None => Some(format!("{} is gone", before)),
+            Some(item) if item.title != *before => Some(format!("{} is titled {} now", before, item.title)),
+            Some(_) => None,
+        },
+        Change::DeleteItem { plex_key, title, before, keep } => match item(plex_key)? {
+            None => Some(format!("{} is gone", title)),
+            Some(item) if item.files(None) != *before => Some(format!("{} has other files now", title)),
+            Some(_) => kept(keep)?,
+        },
+        Change::DeleteMedia { plex_key, media_id, title, before, keep } => match item(plex_key)? {
+            Some(item) if item.media_ids().contains(media_id) && item.files(Some(*media_id)) == *before => kept(keep)?,
+            _ => Some(format!("{} no longer has that copy", title)),
+        },
+        Change::Match { plex_key, title, before, .. } => match item(plex_key)? {
+            None => Some(format!("{} is gone", title)),
+            Some(item) if item.guid != *before => Some(format!("{} is matched to {} now", title, item.guid)),
+            Some(_) => None,
+        },
+        Change::SetField { plex_key, title, field, before, .. } => match item(plex_key)? {
+            None => Some(format!("{} is gone", title)),
+            Some(item) if !item.holds(field, before) => Some(format!("{} of {} is {} now", field, title, item.value(field))),
+            Some(_) => None,
+        },
+        Change::Move { files, .. } => files.iter().find_map(|file| {
+            if !Path::new(&file.before).exists() {
+                Some(format!("{} is gone", file.before))
+            } else if Path::new(&file.after).exists() {
+                Some(format!("{} exists already", file.after))
+            } else {
+                None
+            }
+        }),
+    })
+}
+
+//value is the approved proposal, a rename may have been edited into another title
+fn make(config: &Config, queue: Option<&Queue>, change: &Change, value: &str) -> Result<()> {
+    match change {
+        Change::Rename { section, kind, plex_key, after, language, .. } => match kind.as_str() {
+            "show" => plex::put_plex_show_metadata(config, section, plex_key, value),
+            "episode" => plex::put_plex_episode_metadata(config, section, plex_key, value),
+            _ => {
+                //an edited title is not known to be in the original language
+                let language = Some(language.as_str()).filter(|language| !language.is_empty() && value == after);
+                plex::put_plex_movie_metadata(config, section, plex_key, value, language)
+            }
+        },
+        Change::DeleteItem { plex_key, .. } => plex::delete_plex_metadata(config, plex_key),
+        Change::DeleteMedia { plex_key, media_id, .. } => plex::delete_plex_media(config, plex_key, *media_id),
+        Change::Match { plex_key, after, name, .. } => plex::match_plex_metadata(config, plex_key, after, name),
+        Change::Queue { magnet, .. } => match queue {
+            Some(queue) => queue.deluge.add_torrent_magnet(magnet).map(|_| ()),
+            None => Ok(()),
+        },
+        Change::SetField { section, kind, plex_key, field, .. } => {
+            let plex_type = if kind == "show" { plex::SHOW } else { plex::MOVIE };
+            plex::put_plex_fields(config, section, plex_type, plex_key, &[FieldEdit { field, value, locked: true }])
+        }
+        Change::Move { section, files, .. } => move_files(config, section, files),
+    }
+}
+
+//moves the files, drops the folder they leave empty and has plex scan both folders
+fn move_files(config: &Config, section: &str, files: &[FileMove]) -> Result<()> {
+    let failed = |path: &str, why: std::io::Error| Error::File { path: path.into(), message: why.to_string() };
+    for file in files {
+        if let Some(folder) = Path::new(&file.after).parent() {
+            fs::create_dir_all(folder).map_err(|why| failed(&file.after, why))?;
+        }
+        fs::rename(&file.before, &file.after).map_err(|why| failed(&file.before, why))?;
+    }
+    let folders = files.first()
+        .map(|video| (Path::new(&video.before).parent(), Path::new(&video.after).parent()));
+    if let Some((Some(old), Some(new))) = folders {
+        if fs::read_dir(old).map(|mut entries| entries.next().is_none()).unwrap_or(false) {
+            fs::remove_dir(old).map_err(|why| failed(&old.to_string_lossy(), why))?;
+        }
+        plex::refresh_plex_path(config, section, &old.to_string_lossy())?;
+        if new != old {
+            plex::refresh_plex_path(config, section, &new.to_string_lossy())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    fn rename(plex_key: &str, before: &str, after: &str) -> Change {
+        Change::Rename {
+            section: "1".into(),
+            kind: "movie".into(),
+            plex_key: plex_key.into(),
+            before: before.into(),
+            after: after.into(),
+            reason: String::new(),
+            language: String::new(),
+        }
+    }
+
+    #[test]
+    fn apply_checks_before_state() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(r#"{"MediaContainer":{"Metadata":[{"ratingKey":"1","guid":"local://1","title":"Fyre"}]}}"#),
+            MockResponse::ok(r#"{"MediaContainer":{"Metadata":[{"ratingKey":"2","guid":"local://2","title":"Fyre.2019.1080p"}]}}"#),
+            MockResponse::ok("").status(400),
+            MockResponse::ok(r#"{"MediaContainer":{"Metadata":[{"ratingKey":"3","guid":"local://3","title":"Fyre.2019.1080p"}]}}"#),
+            MockResponse::ok(""),
+        ]);
+        let config = Config::for_test(serde_json::json!({"plex_url": server.url, "plex_token": "secret"}));
+        let path = std::env::temp_dir().join(format!("qable-plan-{}.json", std::process::id()));
+        Plan::new("clean", vec![
+            rename("1", "Fyre.2019.1080p", "Fyre"),
+            rename("2", "Fyre.2019.1080p", "Fyre"),
+            rename("3", "Fyre.2019.1080p", "Fyre"),
+        ]).save(&path).unwrap();
+        let plan = Plan::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(plan.changes[1], rename("2", "Fyre.2019.1080p", "Fyre"));
+
+        //the first item was renamed meanwhile, writing the second fails and the third goes ahead anyway
+        let result = apply(&config, &Approvals::for_test(false, ""), &plan, false);
+        assert!(matches!(result, Err(Error::Status { status: 400, .. })));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[0].path.contains("/library/metadata/1"));
+        assert!(requests[2].method == "PUT" && requests[2].path.contains("id=2"));
+        assert!(requests[4].method == "PUT" && requests[4].path.contains("id=3"));
+    }
+
+    #[test]
+    fn delete_checks_files_and_kept_copy() {
+        let item = |key: &str, media: &str| MockResponse::ok(&format!(
+            r#"{{"MediaContainer":{{"Metadata":[{{"ratingKey":"{}","guid":"local://{}","title":"Fyre","Media":[{}]}}]}}}}"#,
+            key, key, media));
+        let copy = |id: i64, file: &str| format!(r#"{{"id":{},"Part":[{{"id":{},"file":"{}"}}]}}"#, id, id, file);
+        let server = MockServer::start(vec![
+            //rescanned since, with another file
+            item("2", &copy(21, "/movies/b.mkv")),
+            //the copy to keep went away
+            item("1", &copy(12, "/movies/c.mkv")),
+            item("1", &copy(12, "/movies/c.mkv")),
+            //as planned
+            item("3", &copy(31, "/movies/d.mkv")),
+            item("1", &copy(12, "/movies/c.mkv")),
+            MockResponse::ok(""),
+        ]);
+        let config = Config::for_test(serde_json::json!({"plex_url": server.url, "plex_token": "secret"}));
+        let keep = |media_id: i64| KeptCopy { plex_key: "1".into(), media_id, description: "Fyre (2019)".into() };
+        let delete = |plex_key: &str, file: &str, keep: KeptCopy| Change::DeleteItem {
+            plex_key: plex_key.into(),
+            title: "Fyre".into(),
+            before: vec![file.into()],
+            keep,
+        };
+        let plan = Plan::new("dedupe", vec![
+            delete("2", "/movies/a.mkv", keep(11)),
+            Change::DeleteMedia {
+                plex_key: "1".into(),
+                media_id: 12,
+                title: "Fyre".into(),
+                before: vec!["/movies/c.mkv".into()],
+                keep: keep(11),
+            },
+            delete("3", "/movies/d.mkv", keep(12)),
+        ]);
+        apply(&config, &Approvals::for_test(false, ""), &plan, false).unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 6);
+        assert!(requests[..5].iter().all(|r| r.method == "GET"));
+        assert!(requests[5].method == "DELETE" && requests[5].path.contains("/library/metadata/3"));
+    }
+
+    #[test]
+    fn skipped_rename_skips_its_move() {
+        let folder = std::env::temp_dir().join(format!("qable-move-{}", std::process::id()));
+        let video = folder.join("Fyre.2019.1080p.mkv");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(&video, "").unwrap();
+        let config = Config::for_test(serde_json::json!({}));
+        let plan = Plan::new("clean", vec![
+            rename("1", "Fyre.2019.1080p", "Fyre"),
+            Change::Move {
+                section: "1".into(),
+                plex_key: "1".into(),
+                media_id: 11,
+                title: "Fyre".into(),
+                files: vec![FileMove {
+                    before: video.to_string_lossy().into(),
+                    after: folder.join("Fyre (2019).mkv").to_string_lossy().into(),
+                }],
+            },
+        ]);
+        //the move would be accepted, it is not even asked about
+        apply(&config, &Approvals::for_test(true, "n\ny\n"), &plan, false).unwrap();
+        assert!(video.exists());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn original_title_sorts_by_its_language() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(r#"{"MediaContainer":{"Metadata":[{"ratingKey":"1","guid":"local://1","title":"Dolce Vita"}]}}"#),
+            MockResponse::ok(""),
+        ]);
+        let config = Config::for_test(serde_json::json!({"plex_url": server.url, "plex_token": "secret"}));
+        let change = Change::Rename {
+            section: "1".into(),
+            kind: "movie".into(),
+            plex_key: "1".into(),
+            before: "Dolce Vita".into(),
+            after: "La dolce vita".into(),
+            reason: String::new(),
+            language: "it".into(),
+        };
+        apply(&config, &Approvals::for_test(false, ""), &Plan::new("clean", vec![change]), false).unwrap();
+        let requests = server.requests();
+        assert!(requests[1].path.contains("titleSort.value=dolce%20vita,%20La"));
+    }
+}
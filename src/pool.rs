@@ -0,0 +1,46 @@
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+//calls f for every item on up to `workers` threads, the results keep the order of the items
+pub fn map<T: Sync, R: Send>(workers: usize, items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers.clamp(1, items.len().max(1)))
+            .map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    match items.get(index) {
+                        Some(item) => done.push((index, f(item))),
+                        None => return done,
+                    }
+                }
+            }))
+            .collect();
+        handles.into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_else(|why| panic::resume_unwind(why)))
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn results_in_item_order() {
+        let items: Vec<u64> = (0..20).collect();
+        //later items finish first
+        let results = map(4, &items, |&i| {
+            sleep(Duration::from_millis(20 - i));
+            i * 2
+        });
+        assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<u64>>());
+        assert!(map(4, &[] as &[u64], |&i| i).is_empty());
+    }
+}
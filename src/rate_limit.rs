@@ -0,0 +1,88 @@
+use std::sync::{Mutex, PoisonError};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimit;
+
+//token bucket shared by every thread talking to one service
+pub struct RateLimiter {
+    //tokens added per second
+    rate: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    //set by a 429, nothing is sent before then
+    paused_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: &RateLimit) -> RateLimiter {
+        let capacity = f64::from(limit.burst.max(1));
+        RateLimiter {
+            rate: limit.requests_per_second.max(0.1),
+            capacity,
+            bucket: Mutex::new(Bucket { tokens: capacity, updated: Instant::now(), paused_until: None }),
+        }
+    }
+
+    //blocks until a request may be sent
+    pub fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+                let now = Instant::now();
+                match bucket.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ => {
+                        bucket.paused_until = None;
+                        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+                        bucket.updated = now;
+                        if bucket.tokens >= 1.0 {
+                            bucket.tokens -= 1.0;
+                            return;
+                        }
+                        Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+                    }
+                }
+            };
+            sleep(wait);
+        }
+    }
+
+    //holds back every request for the duration, e.g. the Retry-After of a 429
+    pub fn pause(&self, duration: Duration) {
+        let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        let until = Instant::now() + duration;
+        if bucket.paused_until.map(|paused| paused < until).unwrap_or(true) {
+            bucket.paused_until = Some(until);
+        }
+        bucket.tokens = 0.0;
+        bucket.updated = until;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bursts_then_waits_for_tokens() {
+        let limiter = RateLimiter::new(&RateLimit { requests_per_second: 20.0, burst: 2 });
+        let start = Instant::now();
+        limiter.acquire();
+        limiter.acquire();
+        assert!(start.elapsed() < Duration::from_millis(40));
+        limiter.acquire();
+        assert!(start.elapsed() >= Duration::from_millis(45));
+
+        limiter.pause(Duration::from_millis(100));
+        let paused = Instant::now();
+        limiter.acquire();
+        assert!(paused.elapsed() >= Duration::from_millis(140));
+    }
+}
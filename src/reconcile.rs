@@ -0,0 +1,102 @@
+use crate::plex::Metadata;
+use crate::sort;
+use crate::tmdb::MovieDetails;
+
+//plex fields --reconcile knows, by the names plex edits them under
+pub const FIELDS: &[&str] = &["year", "originalTitle", "titleSort", "contentRating", "studio", "summary"];
+
+pub struct Difference {
+    pub field: &'static str,
+    //as plex has it, empty when unset
+    pub plex: String,
+    pub tmdb: String,
+}
+
+//what the field should be according to tmdb, nothing when tmdb does not know.
+//the sort title is the one of the title plex shows, which may have been kept or localized
+fn tmdb_value(field: &str, metadata: &Metadata, details: &MovieDetails, region: &str, articles: &[String]) -> Option<String> {
+    let value = match field {
+        "year" => details.year().to_string(),
+        "originalTitle" => details.original_title.clone(),
+        "titleSort" => sort::sort_title(&metadata.title, articles),
+        "contentRating" => details.certification(region).unwrap_or_default().to_string(),
+        "studio" => details.production_companies.first().map(|c| c.name.clone()).unwrap_or_default(),
+        "summary" => details.overview.clone(),
+        _ => String::new(),
+    };
+    Some(value.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn plex_value(field: &str, metadata: &Metadata) -> String {
+    let info = &metadata.info;
+    match field {
+        "year" => metadata.year.map(|y| y.to_string()),
+        "originalTitle" => info.original_title.clone(),
+        "titleSort" => info.title_sort.clone(),
+        "contentRating" => info.content_rating.clone(),
+        "studio" => info.studio.clone(),
+        "summary" => info.summary.clone(),
+        _ => None,
+    }.unwrap_or_default()
+}
+
+//the selected fields plex and tmdb disagree on, the certification is the one of region
+//and the sort title is made with articles
+pub fn differences(metadata: &Metadata, details: &MovieDetails, fields: &[String], region: &str,
+                   articles: &[String]) -> Vec<Difference> {
+    FIELDS.iter()
+        .filter(|field| fields.iter().any(|f| f == *field))
+        .filter_map(|&field| {
+            let tmdb = tmdb_value(field, metadata, details, region, articles)?;
+            let plex = plex_value(field, metadata);
+            let effective = if field == "titleSort" { sort::effective_title_sort(&metadata.title, &plex) } else { &plex };
+            if effective.trim() == tmdb {
+                return None;
+            }
+            Some(Difference { field, plex, tmdb })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plex::Info;
+
+    #[test]
+    fn differences_per_field() {
+        let metadata = Metadata {
+            section: "1".into(),
+            guid: "com.plexapp.agents.imdb://tt0137523?lang=en".into(),
+            ids: Default::default(),
+            title: "The Fight Club".into(),
+            plex_key: "1".into(),
+            year: Some(1998),
+            duration: None,
+            media: vec![],
+            info: Info {
+                original_title: None,
+                title_sort: None,
+                content_rating: Some("R".into()),
+                studio: Some("Fox 2000 Pictures".into()),
+                summary: Some("A ticking-time-bomb insomniac...".into()),
+            },
+        };
+        let details: MovieDetails = serde_json::from_str(r#"{"id":550,"title":"Fight Club","original_title":"The Fight Club",
+            "release_date":"1999-10-15","overview":"A ticking-time-bomb insomniac...",
+            "production_companies":[{"id":711,"name":"Fox 2000 Pictures"}],
+            "release_dates":{"results":[{"iso_3166_1":"DE","release_dates":[{"certification":"18"}]},
+                                        {"iso_3166_1":"US","release_dates":[{"certification":""},{"certification":"R"}]}]}}"#).unwrap();
+        let all: Vec<String> = FIELDS.iter().map(|f| f.to_string()).collect();
+        let articles = vec!["The".to_string()];
+        let found: Vec<(&str, String, String)> = differences(&metadata, &details, &all, "US", &articles).into_iter()
+            .map(|d| (d.field, d.plex, d.tmdb))
+            .collect();
+        assert_eq!(found, vec![
+            ("year", "1998".to_string(), "1999".to_string()),
+            ("originalTitle", String::new(), "The Fight Club".to_string()),
+            ("titleSort", String::new(), "Fight Club, The".to_string()),
+        ]);
+        assert!(differences(&metadata, &details, &["studio".into(), "summary".into()], "US", &articles).is_empty());
+    }
+}
@@ -0,0 +1,318 @@
+//what a scene release name says about the movie, e.g. Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT
+#[derive(Debug, Default, PartialEq)]
+pub struct Release {
+    pub title: String,
+    pub year: Option<i32>,
+    pub language: Option<String>,
+    //"480p", "720p", "1080p", "2160p"...
+    pub resolution: Option<String>,
+    //"BluRay", "WEB-DL", "WEBRip", "HDTV"...
+    pub source: Option<String>,
+    //codec families as plex reports them, "h264", "hevc"...
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub group: Option<String>,
+    //"Extended", "Director's Cut", "Unrated"...
+    pub edition: Option<String>,
+    pub repack: bool,
+    pub proper: bool,
+}
+
+impl Release {
+    //whether anything besides the title was found
+    pub fn is_release(&self) -> bool {
+        *self != Release { title: self.title.clone(), ..Default::default() }
+    }
+}
+
+enum Tag {
+    Year(i32),
+    Resolution(&'static str),
+    Source(&'static str),
+    VideoCodec(&'static str),
+    AudioCodec(&'static str),
+    Language(&'static str),
+    Edition(&'static str),
+    Repack,
+    Proper,
+}
+
+impl Tag {
+    //resolutions, sources and video codecs never appear in real titles
+    fn is_strong(&self) -> bool {
+        matches!(self, Tag::Resolution(_) | Tag::Source(_) | Tag::VideoCodec(_))
+    }
+}
+
+const VIDEO_EXTENSIONS: &[&str] = &["mkv", "mp4", "avi", "m4v", "mov", "wmv", "mpg"];
+
+//the codec family of x264, avc, x265 and friends, anything else lowercased
+pub fn codec_family(codec: &str) -> String {
+    let codec = codec.to_lowercase();
+    match video_codec(&codec) {
+        Some(family) => family.into(),
+        None => codec,
+    }
+}
+
+fn video_codec(word: &str) -> Option<&'static str> {
+    match word {
+        "x264" | "h264" | "avc" => Some("h264"),
+        "x265" | "h265" | "hevc" => Some("hevc"),
+        "xvid" => Some("xvid"),
+        "divx" => Some("divx"),
+        "av1" => Some("av1"),
+        "vp9" => Some("vp9"),
+        _ => None,
+    }
+}
+
+//word is lowercase without apostrophes
+fn tag(word: &str) -> Option<Tag> {
+    if word.len() == 4 {
+        if let Ok(year) = word.parse::<i32>() {
+            return Some(Tag::Year(year)).filter(|_| (1900..2100).contains(&year));
+        }
+    }
+    if let Some(codec) = video_codec(word) {
+        return Some(Tag::VideoCodec(codec));
+    }
+    Some(match word {
+        "480p" => Tag::Resolution("480p"),
+        "576p" => Tag::Resolution("576p"),
+        "720p" => Tag::Resolution("720p"),
+        "1080p" | "1080i" => Tag::Resolution("1080p"),
+        "2160p" | "4k" | "uhd" => Tag::Resolution("2160p"),
+        "bluray" | "blu-ray" => Tag::Source("BluRay"),
+        "bdrip" => Tag::Source("BDRip"),
+        "brrip" => Tag::Source("BRRip"),
+        "remux" | "bdremux" => Tag::Source("Remux"),
+        "web-dl" | "webdl" => Tag::Source("WEB-DL"),
+        "webrip" => Tag::Source("WEBRip"),
+        "web" => Tag::Source("WEB"),
+        "hdtv" => Tag::Source("HDTV"),
+        "hdrip" => Tag::Source("HDRip"),
+        "dvdrip" => Tag::Source("DVDRip"),
+        "dvdscr" => Tag::Source("DVDScr"),
+        "hdcam" | "cam" => Tag::Source("CAM"),
+        "aac" | "aac2" | "aac5" => Tag::AudioCodec("aac"),
+        "ac3" | "dd" | "dd2" | "dd5" => Tag::AudioCodec("ac3"),
+        "eac3" | "ddp" | "ddp2" | "ddp5" => Tag::AudioCodec("eac3"),
+        "dts" | "dts-hd" | "dts-x" => Tag::AudioCodec("dts"),
+        "truehd" => Tag::AudioCodec("truehd"),
+        "atmos" => Tag::AudioCodec("atmos"),
+        "flac" => Tag::AudioCodec("flac"),
+        "mp3" => Tag::AudioCodec("mp3"),
+        "opus" => Tag::AudioCodec("opus"),
+        "english" | "eng" => Tag::Language("English"),
+        "italian" | "ita" => Tag::Language("Italian"),
+        "french" | "truefrench" | "fre" => Tag::Language("French"),
+        "german" | "ger" => Tag::Language("German"),
+        "spanish" | "spa" => Tag::Language("Spanish"),
+        "japanese" | "jpn" => Tag::Language("Japanese"),
+        "korean" | "kor" => Tag::Language("Korean"),
+        "chinese" | "chi" => Tag::Language("Chinese"),
+        "hindi" => Tag::Language("Hindi"),
+        "russian" | "rus" => Tag::Language("Russian"),
+        "swedish" => Tag::Language("Swedish"),
+        "danish" => Tag::Language("Danish"),
+        "dutch" => Tag::Language("Dutch"),
+        "multi" => Tag::Language("Multi"),
+        "extended" => Tag::Edition("Extended"),
+        "unrated" => Tag::Edition("Unrated"),
+        "uncut" => Tag::Edition("Uncut"),
+        "remastered" => Tag::Edition("Remastered"),
+        "theatrical" => Tag::Edition("Theatrical"),
+        "imax" => Tag::Edition("IMAX"),
+        "criterion" => Tag::Edition("Criterion"),
+        "dc" => Tag::Edition("Director's Cut"),
+        "repack" | "rerip" => Tag::Repack,
+        "proper" => Tag::Proper,
+        _ => return None,
+    })
+}
+
+//tags spread over two words, e.g. Directors.Cut or H.264
+fn pair_tag(word: &str, next: &str) -> Option<Tag> {
+    Some(match (word, next) {
+        ("directors", "cut") => Tag::Edition("Director's Cut"),
+        ("final", "cut") => Tag::Edition("Final Cut"),
+        ("special", "edition") => Tag::Edition("Special Edition"),
+        ("h", "264") => Tag::VideoCodec("h264"),
+        ("h", "265") => Tag::VideoCodec("hevc"),
+        _ => return None,
+    })
+}
+
+fn key(word: &str) -> String {
+    word.to_lowercase().replace(['\'', '’'], "")
+}
+
+pub fn parse(name: &str) -> Release {
+    let name = match name.rsplit_once('.') {
+        Some((stem, extension)) if VIDEO_EXTENSIONS.contains(&extension.to_lowercase().as_str()) => stem,
+        _ => name,
+    };
+    let mut words: Vec<&str> = name.split(['.', ' ', '_', '[', ']', '(', ')', ','])
+        .filter(|w| !w.is_empty())
+        .collect();
+    let tags: Vec<Option<Tag>> = words.iter().map(|w| tag(&key(w))).collect();
+    let is_year = |i: usize| matches!(tags[i], Some(Tag::Year(_)));
+    let known = |i: usize| tags[i].is_some();
+
+    //the title is everything before the year, or before the tags leading up to the first
+    //resolution, source or codec. the first word always belongs to the title
+    let end = match (1..words.len()).find(|&i| tags[i].as_ref().map(Tag::is_strong).unwrap_or(false)) {
+        Some(strong) => match (1..strong).rev().find(|&i| is_year(i)) {
+            Some(year) => year,
+            None => (1..strong).rev().take_while(|&i| known(i)).last().unwrap_or(strong),
+        },
+        //without one, only a trailing year and the tags after it are taken off
+        None => {
+            let trailing: Vec<usize> = (1..words.len()).rev().take_while(|&i| known(i)).collect();
+            match trailing.iter().position(|&i| is_year(i)) {
+                Some(position) => trailing[position],
+                None => words.len(),
+            }
+        }
+    };
+
+    let mut release = Release::default();
+    if end < words.len() {
+        //the group follows the last dash, as in AAC-VXT
+        let last = words.len() - 1;
+        if let Some((rest, group)) = words[last].rsplit_once('-').filter(|_| tag(&key(words[last])).is_none()) {
+            if !group.is_empty() {
+                release.group = Some(group.into());
+                words[last] = rest;
+            }
+        }
+    }
+    //dashes join tags too, as in XviD-AC3
+    let tail: Vec<&str> = words[end..].iter()
+        .flat_map(|w| match tag(&key(w)) {
+            None => w.split('-').collect(),
+            Some(_) => vec![*w],
+        })
+        .collect();
+    words.truncate(end);
+    words.extend(tail);
+    let mut i = end;
+    while i < words.len() {
+        let word = key(words[i]);
+        let (tag, width) = match words.get(i + 1).and_then(|next| pair_tag(&word, &key(next))) {
+            Some(tag) => (Some(tag), 2),
+            None => (tag(&word), 1),
+        };
+        match tag {
+            Some(Tag::Year(year)) => { release.year.get_or_insert(year); }
+            Some(Tag::Resolution(r)) => { release.resolution.get_or_insert(r.into()); }
+            Some(Tag::Source(s)) => { release.source.get_or_insert(s.into()); }
+            Some(Tag::VideoCodec(c)) => { release.video_codec.get_or_insert(c.into()); }
+            Some(Tag::AudioCodec(c)) => { release.audio_codec.get_or_insert(c.into()); }
+            Some(Tag::Language(l)) => { release.language.get_or_insert(l.into()); }
+            Some(Tag::Edition(e)) => { release.edition.get_or_insert(e.into()); }
+            Some(Tag::Repack) => release.repack = true,
+            Some(Tag::Proper) => release.proper = true,
+            None => {}
+        }
+        i += width;
+    }
+    release.title = words[..end].join(" ");
+    release
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.into())
+    }
+
+    #[test]
+    fn parse_release_names() {
+        let cases = vec![
+            ("Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT", Release {
+                title: "Eight and a Half".into(), year: Some(1963), language: some("Italian"), resolution: some("1080p"),
+                source: some("BluRay"), video_codec: some("h264"), audio_codec: some("aac"), group: some("VXT"),
+                ..Default::default()
+            }),
+            ("Movie 1080p BluRay x264", Release {
+                title: "Movie".into(), resolution: some("1080p"), source: some("BluRay"), video_codec: some("h264"),
+                ..Default::default()
+            }),
+            ("The.Matrix.1999.REMASTERED.2160p.UHD.BluRay.x265.10bit.HDR.TrueHD.7.1.Atmos-SWTYBLZ", Release {
+                title: "The Matrix".into(), year: Some(1999), resolution: some("2160p"), source: some("BluRay"),
+                video_codec: some("hevc"), audio_codec: some("truehd"), edition: some("Remastered"), group: some("SWTYBLZ"),
+                ..Default::default()
+            }),
+            ("Blade.Runner.2049.2017.1080p.WEB-DL.DD5.1.H.264-FGT.mkv", Release {
+                title: "Blade Runner 2049".into(), year: Some(2017), resolution: some("1080p"), source: some("WEB-DL"),
+                video_codec: some("h264"), audio_codec: some("ac3"), group: some("FGT"),
+                ..Default::default()
+            }),
+            ("Apocalypse.Now.1979.Final.Cut.720p.BRRip.x264.AAC-ETRG", Release {
+                title: "Apocalypse Now".into(), year: Some(1979), resolution: some("720p"), source: some("BRRip"),
+                video_codec: some("h264"), audio_codec: some("aac"), edition: some("Final Cut"), group: some("ETRG"),
+                ..Default::default()
+            }),
+            ("Kingdom.of.Heaven.2005.Directors.Cut.1080p.BluRay.DTS.x264-CtrlHD", Release {
+                title: "Kingdom of Heaven".into(), year: Some(2005), resolution: some("1080p"), source: some("BluRay"),
+                video_codec: some("h264"), audio_codec: some("dts"), edition: some("Director's Cut"), group: some("CtrlHD"),
+                ..Default::default()
+            }),
+            ("Fight.Club.1999.REPACK.PROPER.720p.BluRay.x264-SiNNERS", Release {
+                title: "Fight Club".into(), year: Some(1999), resolution: some("720p"), source: some("BluRay"),
+                video_codec: some("h264"), group: some("SiNNERS"), repack: true, proper: true,
+                ..Default::default()
+            }),
+            ("Amelie.2001.FRENCH.DVDRip.XviD-AC3-NoTV", Release {
+                title: "Amelie".into(), year: Some(2001), language: some("French"), source: some("DVDRip"),
+                video_codec: some("xvid"), audio_codec: some("ac3"), group: some("NoTV"),
+                ..Default::default()
+            }),
+            ("Spider-Man.Into.the.Spider-Verse.2018.2160p.WEBRip.x265.10bit.HDR.DDP5.1.Atmos-NOGRP", Release {
+                title: "Spider-Man Into the Spider-Verse".into(), year: Some(2018), resolution: some("2160p"),
+                source: some("WEBRip"), video_codec: some("hevc"), audio_codec: some("eac3"), group: some("NOGRP"),
+                ..Default::default()
+            }),
+            ("Parasite (2019) [1080p] [BluRay] [5.1] [YTS.MX]", Release {
+                title: "Parasite".into(), year: Some(2019), resolution: some("1080p"), source: some("BluRay"),
+                ..Default::default()
+            }),
+            ("Oldboy 2003 KOREAN 1080p HDRip HEVC", Release {
+                title: "Oldboy".into(), year: Some(2003), language: some("Korean"), resolution: some("1080p"),
+                source: some("HDRip"), video_codec: some("hevc"),
+                ..Default::default()
+            }),
+            ("The_Thing_1982_UNRATED_480p_HDTV", Release {
+                title: "The Thing".into(), year: Some(1982), resolution: some("480p"), source: some("HDTV"),
+                edition: some("Unrated"),
+                ..Default::default()
+            }),
+            ("Leon.EXTENDED.1080p.BluRay.x264", Release {
+                title: "Leon".into(), resolution: some("1080p"), source: some("BluRay"), video_codec: some("h264"),
+                edition: some("Extended"),
+                ..Default::default()
+            }),
+            ("Fyre (2019)", Release { title: "Fyre".into(), year: Some(2019), ..Default::default() }),
+            ("1917.2019.1080p.WEBRip.x264-RARBG", Release {
+                title: "1917".into(), year: Some(2019), resolution: some("1080p"), source: some("WEBRip"),
+                video_codec: some("h264"), group: some("RARBG"),
+                ..Default::default()
+            }),
+            ("2001 A Space Odyssey", Release { title: "2001 A Space Odyssey".into(), ..Default::default() }),
+            ("The Italian Job", Release { title: "The Italian Job".into(), ..Default::default() }),
+            ("Rocky II Extended", Release { title: "Rocky II Extended".into(), ..Default::default() }),
+            ("Star Wars: Episode IV - A New Hope", Release {
+                title: "Star Wars: Episode IV - A New Hope".into(), ..Default::default()
+            }),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(parse(name), expected, "{}", name);
+        }
+        assert!(!parse("The Italian Job").is_release());
+        assert_eq!(codec_family("AVC"), "h264");
+    }
+}
@@ -1,76 +1,245 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::thread::sleep;
-use std::time;
+use std::time::Duration;
 
-use ureq::{Response};
+use serde::de::DeserializeOwned;
+use ureq::{Agent, Request, Response};
 
-pub fn post_response(url: &str,
-                    headers: &[(&str, &str)],
-                    queries: &[(&str, &str)],
-                    data: serde_json::Value) -> Response {
-    let mut post = ureq::post(url);
-    for header in headers {
-        post.set(header.0, header.1);
+use crate::cassette::Cassette;
+use crate::config::{Config, RetryPolicy};
+use crate::error::{Error, Result};
+use crate::rate_limit::RateLimiter;
+
+//turns synthetic responses into transport errors and unsuccessful ones into status errors
+fn check(url: &str, response: Response) -> Result<Response> {
+    if let Some(why) = response.synthetic_error() {
+        return Err(Error::Transport { url: url.into(), message: why.to_string() });
     }
-    for query in queries {
-        post.query(query.0, query.1);
+    if !response.ok() {
+        return Err(Error::Status {
+            url: url.into(),
+            status: response.status(),
+            message: response.status_text().into(),
+            //only the delta-seconds form, http dates fall back to the backoff
+            retry_after: response.header("Retry-After").and_then(|seconds| seconds.trim().parse().ok()),
+        });
     }
-    post.send_json(data)
+    Ok(response)
 }
 
-pub fn put_response(url: &str,
-                    headers: &[(&str, &str)],
-                    queries: &[(&str, &str)]) -> Response {
-    let mut put = ureq::put(url);
-    for header in headers {
-        put.set(header.0, header.1);
+//the json body of a successful response
+pub fn decode<T: DeserializeOwned>(response: Box<Response>) -> Result<T> {
+    let url = response.get_url().to_string();
+    serde_json::from_reader(response.into_reader()).map_err(|why| Error::decode(&url, why))
+}
+
+//how long to wait before the attempt following the given one, doubling each time up to the
+//max delay, with up to half of it taken off at random so clients don't retry in lockstep
+fn delay(policy: &RetryPolicy, attempt: u32) -> Duration {
+    let millis = policy.base_delay_millis
+        .saturating_mul(1u64 << (attempt.saturating_sub(1)).min(32))
+        .min(policy.max_delay_millis);
+    let jitter = RandomState::new().build_hasher().finish() % (millis / 2 + 1);
+    Duration::from_millis(millis - jitter)
+}
+
+fn retryable(policy: &RetryPolicy, why: &Error) -> bool {
+    match why {
+        Error::Transport { .. } => true,
+        Error::Status { status, .. } => policy.retry_statuses.contains(status),
+        Error::Config(_) | Error::Decode { .. } | Error::NotFound(_) | Error::File { .. } => false,
     }
-    for query in queries {
-        put.query(query.0, query.1);
+}
+
+//sends until the request succeeds, fails for good or runs out of attempts
+fn with_retries<T>(policy: &RetryPolicy, limiter: Option<&RateLimiter>, send: impl Fn() -> Result<T>) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        if let Some(limiter) = limiter {
+            limiter.acquire();
+        }
+        let result = send();
+        let why = match &result {
+            Err(why) if attempt < u32::from(policy.attempts) && retryable(policy, why) => why,
+            _ => return result,
+        };
+        //a server asking for a day is not waited for longer than max_delay_millis
+        let max_delay = Duration::from_millis(policy.max_delay_millis);
+        match (why, limiter) {
+            (Error::Status { retry_after: Some(seconds), .. }, Some(limiter)) => {
+                limiter.pause(Duration::from_secs(*seconds).min(max_delay))
+            }
+            (Error::Status { retry_after: Some(seconds), .. }, None) => sleep(Duration::from_secs(*seconds).min(max_delay)),
+            _ => sleep(delay(policy, attempt)),
+        }
     }
-    put.call()
 }
 
-fn get_response(url: &str,
-                headers: &[(&str, &str)],
-                queries: &[(&str, &str)]) -> Response {
-    let mut get = ureq::get(url);
+//how requests to one service are sent
+pub struct Client<'a> {
+    pub policy: RetryPolicy,
+    //answers instead of the server when replaying, keeps what the server said when recording
+    pub cassette: Option<&'a Cassette>,
+}
+
+pub fn client<'a>(config: &'a Config, service: &str) -> Client<'a> {
+    Client { policy: config.retry_policy(service), cassette: config.cassette.as_ref() }
+}
+
+fn send(client: &Client, mut request: Request, data: Option<&serde_json::Value>) -> Result<Response> {
+    let base_url = request.get_url().split('?').next().unwrap_or_default().to_string();
+    let url = format!("{}{}", base_url, request.get_query().unwrap_or_default());
+    let method = request.get_method().to_string();
+    let body = data.map(|d| d.to_string());
+    let response = match client.cassette {
+        Some(cassette) if cassette.replaying() => cassette.play(&method, &url, body.as_deref())?,
+        cassette => {
+            let response = match data {
+                Some(data) => request.send_json(data.clone()),
+                None => request.call(),
+            };
+            match cassette {
+                Some(cassette) if !response.synthetic() => cassette.save(&method, &url, body.as_deref(), response)?,
+                _ => response,
+            }
+        }
+    };
+    check(&base_url, response)
+}
+
+fn with_query(mut request: Request, headers: &[(&str, &str)], queries: &[(&str, &str)]) -> Request {
     for header in headers {
-        get.set(header.0, header.1);
+        request.set(header.0, header.1);
     }
     for query in queries {
-        get.query(query.0, query.1);
+        request.query(query.0, query.1);
     }
-    get.call()
+    request
+}
+
+//posts through the given agent so cookies persist between calls, only sent again when the policy retries posts
+pub fn post_agent_response(agent: &Agent,
+                           url: &str,
+                           headers: &[(&str, &str)],
+                           queries: &[(&str, &str)],
+                           data: serde_json::Value,
+                           client: &Client) -> Result<Response> {
+    let once = RetryPolicy { attempts: 1, ..client.policy.clone() };
+    let policy = if client.policy.retry_posts { &client.policy } else { &once };
+    with_retries(policy, None, || send(client, with_query(agent.post(url), headers, queries), Some(&data)))
+}
+
+pub fn put_response(url: &str,
+                    headers: &[(&str, &str)],
+                    queries: &[(&str, &str)],
+                    client: &Client) -> Result<Response> {
+    with_retries(&client.policy, None, || send(client, with_query(ureq::put(url), headers, queries), None))
+}
+
+pub fn delete_response(url: &str,
+                       headers: &[(&str, &str)],
+                       queries: &[(&str, &str)],
+                       client: &Client) -> Result<Response> {
+    with_retries(&client.policy, None, || send(client, with_query(ureq::delete(url), headers, queries), None))
+}
+
+fn get_response(client: &Client,
+                url: &str,
+                headers: &[(&str, &str)],
+                queries: &[(&str, &str)]) -> Result<Response> {
+    send(client, with_query(ureq::get(url), headers, queries), None)
 }
 
+//retries until the handler accepts the response, or the failure is not worth retrying
 pub fn get_response_data<T>(url: &str,
                             headers: &[(&str, &str)],
                             query: &[(&str, &str)],
-                            api_backoff_millis: u64,
-                            retries: u8,
-                            ok_handler: impl Fn(Box<Response>) -> (bool, Option<T>))
-                            -> Option<T> {
-    let mut data: Option<T> = None;
-    let mut complete = false;
-    let mut backoff = api_backoff_millis;
-    let mut attempts = 0;
-
-    while !complete {
-        attempts += 1;
-        let response = get_response(url, headers, query);
-        if response.ok() {
-            //TODO: destructure response when it's supported
-            let r = ok_handler(Box::new(response));
-            complete = r.0;
-            data = r.1;
-        }
-        if !complete {
-            if attempts >= retries {
-                complete = true;
-            }
-            backoff += api_backoff_millis;
-            sleep(time::Duration::from_millis(backoff));
+                            client: &Client,
+                            ok_handler: impl Fn(Box<Response>) -> Result<T>)
+                            -> Result<T> {
+    //TODO: destructure response when it's supported
+    with_retries(&client.policy, None,
+                 || get_response(client, url, headers, query).and_then(|response| ok_handler(Box::new(response))))
+}
+
+//like get_response_data, every attempt waits for the limiter and a 429 pauses all its users
+pub fn get_rate_limited_data<T>(limiter: &RateLimiter,
+                                url: &str,
+                                headers: &[(&str, &str)],
+                                query: &[(&str, &str)],
+                                client: &Client,
+                                ok_handler: impl Fn(Box<Response>) -> Result<T>)
+                                -> Result<T> {
+    with_retries(&client.policy, Some(limiter),
+                 || get_response(client, url, headers, query).and_then(|response| ok_handler(Box::new(response))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    #[test]
+    fn errors_by_kind() {
+        let server = MockServer::start(vec![
+            MockResponse::ok("{}").status(503),
+            MockResponse::ok("not json"),
+        ]);
+        let client = Client { policy: RetryPolicy { attempts: 1, ..Default::default() }, cassette: None };
+        let fetch = || get_response_data(&server.url, &[], &[], &client, decode::<serde_json::Value>);
+        assert!(matches!(fetch(), Err(Error::Status { status: 503, .. })));
+        assert!(matches!(fetch(), Err(Error::Decode { .. })));
+        //the mock server stopped listening
+        assert!(matches!(fetch(), Err(Error::Transport { .. })));
+    }
+
+    #[test]
+    fn honors_retry_after() {
+        let server = MockServer::start(vec![
+            MockResponse::ok("{}").status(429).header("Retry-After", "1"),
+            MockResponse::ok(r#"{"id":550}"#),
+            //a 503 may ask too, but not for longer than max_delay_millis
+            MockResponse::ok("{}").status(503).header("Retry-After", "86400"),
+            MockResponse::ok(r#"{"id":551}"#),
+        ]);
+        let limiter = RateLimiter::new(&Default::default());
+        let start = std::time::Instant::now();
+        let client = Client { policy: RetryPolicy { attempts: 2, max_delay_millis: 1500, ..Default::default() }, cassette: None };
+        let data = get_rate_limited_data(&limiter, &server.url, &[], &[], &client, decode::<serde_json::Value>);
+        assert_eq!(data.unwrap()["id"], 550);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        let data = get_response_data(&server.url, &[], &[], &client, decode::<serde_json::Value>);
+        assert_eq!(data.unwrap()["id"], 551);
+        assert!(start.elapsed() >= Duration::from_millis(2500) && start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn retries_only_what_may_succeed() {
+        let server = MockServer::start(vec![
+            MockResponse::ok("{}").status(503),
+            MockResponse::ok("{}").status(404),
+            MockResponse::ok("{}").status(503),
+            MockResponse::ok("{}"),
+        ]);
+        let client = Client { policy: RetryPolicy { attempts: 3, base_delay_millis: 1, ..Default::default() }, cassette: None };
+        let deleted = delete_response(&server.url, &[], &[], &client);
+        assert!(matches!(deleted, Err(Error::Status { status: 404, .. })));
+        assert_eq!(server.requests().len(), 2);
+        //a post that failed may have been acted on, it is not sent again
+        let posted = post_agent_response(&ureq::agent(), &server.url, &[], &[], serde_json::json!({}), &Client {
+            policy: RetryPolicy { attempts: 3, base_delay_millis: 1, ..Default::default() },
+            cassette: None,
+        });
+        assert!(matches!(posted, Err(Error::Status { status: 503, .. })));
+        assert_eq!(server.requests().len(), 3);
+
+        let policy = RetryPolicy { base_delay_millis: 1000, max_delay_millis: 4000, ..Default::default() };
+        for attempt in 1..10 {
+            let expected = (1000u64 << (attempt - 1)).min(4000);
+            let millis = delay(&policy, attempt).as_millis() as u64;
+            assert!(millis <= expected && millis >= expected / 2);
         }
     }
-    data
-}
\ No newline at end of file
+}
@@ -0,0 +1,96 @@
+use crate::config::Config;
+use crate::tmdb::MovieDetails;
+
+//numbers are padded to this many digits, so 9 sorts before 10
+const NUMBER_WIDTH: usize = 4;
+
+//"The Matrix" sorts as "Matrix, The", "L'Avventura" as "Avventura, L'" and "Ocean's 11" as "Ocean's 0011".
+//leading punctuation like in "'Round Midnight" is dropped
+pub fn sort_title(title: &str, articles: &[String]) -> String {
+    let title = title.trim_start_matches(|c: char| !c.is_alphanumeric()).trim_end();
+    let moved = articles.iter()
+        .filter_map(|article| {
+            let head = title.get(..article.len()).filter(|head| head.eq_ignore_ascii_case(article))?;
+            //elided articles like L' run into the word, the others end at a space
+            let rest = if article.ends_with('\'') { &title[head.len()..] } else { title[head.len()..].strip_prefix(' ')? };
+            Some(format!("{}, {}", rest.trim_start(), head)).filter(|_| !rest.trim().is_empty())
+        })
+        .next()
+        .unwrap_or_else(|| title.to_string());
+    pad_numbers(&moved)
+}
+
+fn pad_numbers(title: &str) -> String {
+    let mut padded = String::new();
+    let mut digits = String::new();
+    for c in title.chars().chain(std::iter::once('\0')) {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        if !digits.is_empty() {
+            padded.push_str(&format!("{:0>width$}", digits, width = NUMBER_WIDTH));
+            digits.clear();
+        }
+        if c != '\0' {
+            padded.push(c);
+        }
+    }
+    padded
+}
+
+//plex leaves titleSort out while it is the title, so an empty one sorts as the title
+pub fn effective_title_sort<'a>(title: &'a str, title_sort: &'a str) -> &'a str {
+    if title_sort.trim().is_empty() { title } else { title_sort }
+}
+
+//the articles of the language the title is in, which tmdb tells when it is the original title,
+//otherwise those of sort_language
+pub fn articles<'a>(config: &'a Config, title: &str, details: Option<&MovieDetails>) -> &'a [String] {
+    language_articles(config, details
+        .filter(|details| details.original_title == title)
+        .map(|details| details.original_language.as_str()))
+}
+
+//the articles of the language, those of sort_language when it is unknown or has none
+pub fn language_articles<'a>(config: &'a Config, language: Option<&str>) -> &'a [String] {
+    let language = language
+        .filter(|language| config.sort_articles.contains_key(*language))
+        .unwrap_or(&config.sort_language);
+    config.sort_articles.get(language).map(Vec::as_slice).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sort_titles() {
+        let config = Config::for_test(serde_json::json!({}));
+        let english = articles(&config, "The Matrix", None);
+        let italian = &config.sort_articles["it"];
+        let cases: &[(&str, &[String], &str)] = &[
+            ("The Matrix", english, "Matrix, The"),
+            ("A Beautiful Mind", english, "Beautiful Mind, A"),
+            ("An American Werewolf in London", english, "American Werewolf in London, An"),
+            ("A.I. Artificial Intelligence", english, "A.I. Artificial Intelligence"),
+            ("Theater Camp", english, "Theater Camp"),
+            ("The", english, "The"),
+            ("'Round Midnight", english, "Round Midnight"),
+            ("...And Justice for All", english, "And Justice for All"),
+            ("Ocean's 11", english, "Ocean's 0011"),
+            ("2001: A Space Odyssey", english, "2001: A Space Odyssey"),
+            ("The 13th Warrior", english, "0013th Warrior, The"),
+            ("L'avventura", italian, "avventura, L'"),
+            ("La dolce vita", italian, "dolce vita, La"),
+            ("La dolce vita", english, "La dolce vita"),
+        ];
+        for (title, articles, expected) in cases {
+            assert_eq!(sort_title(title, articles), *expected, "{}", title);
+        }
+        assert_eq!(language_articles(&config, Some("it")), italian.as_slice());
+        assert_eq!(language_articles(&config, Some("xx")), english);
+        assert_eq!(effective_title_sort("Fyre", ""), "Fyre");
+        assert_eq!(effective_title_sort("The Matrix", "Matrix, The"), "Matrix, The");
+    }
+}
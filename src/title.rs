@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::config::{Config, TitlePolicy};
+use crate::release;
+use crate::tmdb::MovieTitle;
+
+//why a plex title is or is not replaced by the tmdb title
+#[derive(Debug, PartialEq)]
+pub enum Reason {
+    //the same words, at most punctuation, case or accents differ
+    Same,
+    //the plex title is a release name, e.g. "Movie 1080p BluRay x264"
+    ReleaseNoise(Vec<String>),
+    //similar enough to leave alone
+    Similar(f64),
+    Different(f64),
+}
+
+impl Reason {
+    pub fn is_dirty(&self) -> bool {
+        matches!(self, Reason::ReleaseNoise(_) | Reason::Different(_))
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Same => write!(f, "same title"),
+            Reason::ReleaseNoise(noise) => write!(f, "release name noise: {}", noise.join(" ")),
+            Reason::Similar(score) => write!(f, "similar, {:.2}", score),
+            Reason::Different(score) => write!(f, "different, {:.2}", score),
+        }
+    }
+}
+
+const NUMBERS: &[&str] = &[
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen", "nineteen", "twenty",
+];
+
+//"i", "v" and "x" are too often words or letters
+const ROMAN: &[&str] = &[
+    "", "", "ii", "iii", "iv", "", "vi", "vii", "viii", "ix", "",
+    "xi", "xii", "xiii", "xiv", "xv", "xvi", "xvii", "xviii", "xix", "xx",
+];
+
+fn fold(c: char) -> &'static str {
+    match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'č' => "c",
+        'ď' | 'đ' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'ğ' => "g",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'ı' => "i",
+        'ł' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'œ' => "oe",
+        'ř' => "r",
+        'ś' | 'š' | 'ş' => "s",
+        'ß' => "ss",
+        'ť' => "t",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        '½' => " half ",
+        '&' => " and ",
+        _ => "",
+    }
+}
+
+//lowercase words without accents, split on anything that is not a letter or digit
+fn words(title: &str) -> Vec<String> {
+    let mut folded = String::new();
+    for c in title.chars().flat_map(char::to_lowercase) {
+        match fold(c) {
+            "" if c.is_alphanumeric() => folded.push(c),
+            "" => folded.push(' '),
+            replacement => folded.push_str(replacement),
+        }
+    }
+    folded.split_whitespace().map(String::from).collect()
+}
+
+//spelled out and roman numbers become digits
+fn normalize(word: String) -> String {
+    NUMBERS.iter().chain(ROMAN.iter())
+        .position(|n| !n.is_empty() && **n == word)
+        .map(|index| (index % NUMBERS.len()).to_string())
+        .unwrap_or(word)
+}
+
+pub fn tokens(title: &str) -> Vec<String> {
+    words(title).into_iter().map(normalize).collect()
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+//the better of the edit similarity of the joined tokens and the share of tokens in common, 0 to 1
+pub fn similarity(a: &[String], b: &[String]) -> f64 {
+    let (joined_a, joined_b): (Vec<char>, Vec<char>) = (a.join(" ").chars().collect(), b.join(" ").chars().collect());
+    let longest = joined_a.len().max(joined_b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let edit = 1.0 - levenshtein(&joined_a, &joined_b) as f64 / longest as f64;
+    let (set_a, set_b): (HashSet<&String>, HashSet<&String>) = (a.iter().collect(), b.iter().collect());
+    let token = set_a.intersection(&set_b).count() as f64 / set_a.union(&set_b).count() as f64;
+    edit.max(token)
+}
+
+//whether the plex title should give way to the tmdb title, titles scoring below threshold are replaced
+pub fn compare(plex_title: &str, tmdb_title: &str, threshold: f64) -> Reason {
+    let tmdb_tokens = tokens(tmdb_title);
+    if tokens(plex_title) == tmdb_tokens {
+        return Reason::Same;
+    }
+    let release = release::parse(plex_title);
+    if release.is_release() {
+        let plex_words = words(plex_title);
+        return Reason::ReleaseNoise(plex_words[words(&release.title).len()..].to_vec());
+    }
+    let score = similarity(&tokens(plex_title), &tmdb_tokens);
+    if score < threshold {
+        Reason::Different(score)
+    } else {
+        Reason::Similar(score)
+    }
+}
+
+//the tmdb title the policy gives the movie
+pub fn preferred<'a>(config: &Config, movie: &'a MovieTitle) -> &'a str {
+    let original = config.title_policy == TitlePolicy::Original
+        && !movie.original_title.is_empty()
+        && config.original_languages.contains(&movie.original_language);
+    if original { &movie.original_title } else { &movie.title }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn title_policies() {
+        let movie = MovieTitle {
+            title: "The Great Beauty".into(),
+            original_title: "La grande bellezza".into(),
+            original_language: "it".into(),
+        };
+        let policy = |policy: &str, languages: &[&str]| {
+            let config = Config::for_test(serde_json::json!({"title_policy": policy, "original_languages": languages}));
+            preferred(&config, &movie).to_string()
+        };
+        assert_eq!(policy("localized", &["it"]), "The Great Beauty");
+        assert_eq!(policy("original", &["it", "fr"]), "La grande bellezza");
+        assert_eq!(policy("original", &["fr"]), "The Great Beauty");
+        assert_eq!(policy("both", &["it"]), "The Great Beauty");
+    }
+
+    #[test]
+    fn compare_titles() {
+        let cases: &[(&str, &str, &str)] = &[
+            ("Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT", "8½",
+             "release name noise: 1963 italian 1080p bluray h264 aac vxt"),
+            ("Movie 1080p BluRay x264", "Movie", "release name noise: 1080p bluray x264"),
+            ("Fyre (2019)", "Fyre", "release name noise: 2019"),
+            ("The Italian Job", "The Italian Job", "same title"),
+            ("Star Wars Episode IV A New Hope", "Star Wars: Episode IV - A New Hope", "same title"),
+            ("Amelie", "Amélie", "same title"),
+            ("Rocky Two", "Rocky II", "same title"),
+            ("Fast & Furious", "Fast and Furious", "same title"),
+            ("Blade Runner 2049", "Blade Runner 2049", "same title"),
+            ("2001 A Space Odyssey", "2001: A Space Odyssey", "same title"),
+            ("The Lord of the Rings The Felowship of the Ring", "The Lord of the Rings: The Fellowship of the Ring",
+             "similar, 0.98"),
+            ("Untitled", "Fight Club", "different, 0.20"),
+        ];
+        for (plex, tmdb, reason) in cases {
+            assert_eq!(compare(plex, tmdb, 0.8).to_string(), *reason, "{} vs {}", plex, tmdb);
+        }
+        assert!(compare("Eight.and.a.Half.1963.ITALIAN.1080p.BluRay.H264.AAC-VXT", "8½", 0.8).is_dirty());
+        //long titles used to overflow
+        assert!(!compare(&"Very Long Title ".repeat(20), &"Very Long Title ".repeat(20), 0.8).is_dirty());
+    }
+}
@@ -1,42 +1,467 @@
-use serde::Deserialize;
+use std::sync::OnceLock;
 
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+use crate::cache::{Cache, Source};
 use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::plex::ExternalIds;
+use crate::rate_limit::RateLimiter;
 use crate::request;
 
 #[derive(Deserialize)]
 struct FindResponse {
-    movie_results: Vec<FindMovieResults>
+    #[serde(default)]
+    movie_results: Vec<FindMovieResults>,
+    #[serde(default)]
+    tv_results: Vec<FindTvResults>,
 }
 
 #[derive(Deserialize)]
 struct FindMovieResults {
-    id: i32,
-    title: String,
-}
-
-pub fn get_movie_title(config: &Config, imdb_id: &str) -> Option<String> {
-    request::get_response_data(&format!("https://api.themoviedb.org/3/find/{}", imdb_id),
-                      &[
-                          ("Authorization", &format!("Bearer {}", config.tmdb_v4_api_key)),
-                          ("Content-Type", "application/json;charset=utf-8"),
-                          ("Accept", "application/json")
-                      ],
-                      &[
-                          ("language", "en-US"),
-                          ("external_source", "imdb_id")
-                      ],
-                      config.api_backoff_millis,
-                      config.retries,
-                      |response| -> (bool, Option<String>) {
-                          match serde_json::from_str::<FindResponse>(&response.into_string().unwrap()) {
-                              Err(_) => (false, None),
-                              Ok(find_results) => {
-                                  if find_results.movie_results.len() == 1 {
-                                      (true, find_results.movie_results.first().map(|x| x.title.clone()))
-                                  } else {
-                                      (false, None)
-                                  }
-                              }
-                          }
-                      })
-}
\ No newline at end of file
+    id: i64,
+    #[serde(flatten)]
+    title: MovieTitle,
+}
+
+//the localized title along with the original one, see get_movie_title
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MovieTitle {
+    pub title: String,
+    #[serde(default)]
+    pub original_title: String,
+    //"en", "it"...
+    #[serde(default)]
+    pub original_language: String,
+}
+
+#[derive(Deserialize)]
+struct FindTvResults {
+    id: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TvDetails {
+    pub id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub seasons: Vec<TvSeason>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TvSeason {
+    pub season_number: u32,
+    pub episode_count: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TvSeasonDetails {
+    #[serde(default)]
+    pub episodes: Vec<TvEpisode>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TvEpisode {
+    pub episode_number: u32,
+    pub name: String,
+    //"YYYY-MM-DD", missing for episodes not yet scheduled
+    pub air_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    results: Vec<SearchResult>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchResult {
+    pub id: i64,
+    pub title: String,
+    #[serde(default)]
+    pub release_date: String,
+}
+
+impl SearchResult {
+    pub fn year(&self) -> &str {
+        self.release_date.get(..4).unwrap_or_default()
+    }
+}
+
+//a movie along with what get_movie_details appends to it: release dates, credits, external ids,
+//images and keywords
+#[derive(Serialize, Deserialize)]
+pub struct MovieDetails {
+    pub id: i64,
+    #[serde(default)]
+    pub imdb_id: Option<String>,
+    pub title: String,
+    #[serde(default)]
+    pub original_title: String,
+    //"en", "it"...
+    #[serde(default)]
+    pub original_language: String,
+    //"YYYY-MM-DD"
+    #[serde(default)]
+    pub release_date: String,
+    #[serde(default)]
+    pub overview: String,
+    #[serde(default)]
+    pub tagline: String,
+    //minutes
+    #[serde(default)]
+    pub runtime: Option<u32>,
+    #[serde(default)]
+    pub genres: Vec<Genre>,
+    #[serde(default)]
+    pub production_companies: Vec<Company>,
+    //the collection the movie is part of, e.g. a trilogy
+    #[serde(default)]
+    pub belongs_to_collection: Option<Collection>,
+    #[serde(default)]
+    pub release_dates: ReleaseDates,
+    #[serde(default)]
+    pub credits: Credits,
+    #[serde(default)]
+    pub external_ids: MovieExternalIds,
+    #[serde(default)]
+    pub images: Images,
+    #[serde(default)]
+    pub keywords: Keywords,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Genre {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Collection {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Credits {
+    #[serde(default)]
+    pub cast: Vec<CastMember>,
+    #[serde(default)]
+    pub crew: Vec<CrewMember>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CastMember {
+    pub id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub character: String,
+    //billing position, 0 first
+    #[serde(default)]
+    pub order: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CrewMember {
+    pub id: i64,
+    pub name: String,
+    //"Director", "Screenplay"...
+    #[serde(default)]
+    pub job: String,
+    #[serde(default)]
+    pub department: String,
+}
+
+//empty strings and nulls alike when tmdb does not know the id
+#[derive(Serialize, Deserialize, Default)]
+pub struct MovieExternalIds {
+    #[serde(default)]
+    pub imdb_id: Option<String>,
+    #[serde(default)]
+    pub wikidata_id: Option<String>,
+    #[serde(default)]
+    pub facebook_id: Option<String>,
+    #[serde(default)]
+    pub instagram_id: Option<String>,
+    #[serde(default)]
+    pub twitter_id: Option<String>,
+}
+
+//images in the tmdb language and without text, see get_movie_details
+#[derive(Serialize, Deserialize, Default)]
+pub struct Images {
+    #[serde(default)]
+    pub backdrops: Vec<Image>,
+    #[serde(default)]
+    pub posters: Vec<Image>,
+    #[serde(default)]
+    pub logos: Vec<Image>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Image {
+    //relative to the image base url, e.g. "/pB8BM7pdSp6B6Ih7QZ4DrQ3PmJK.jpg"
+    pub file_path: String,
+    //null for images without text
+    #[serde(default)]
+    pub iso_639_1: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub vote_average: f64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Keywords {
+    #[serde(default)]
+    pub keywords: Vec<Keyword>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Keyword {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Company {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ReleaseDates {
+    pub results: Vec<CountryReleases>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CountryReleases {
+    //"US", "DE"...
+    pub iso_3166_1: String,
+    pub release_dates: Vec<ReleaseDate>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReleaseDate {
+    //"PG-13", "R", empty when unrated
+    #[serde(default)]
+    pub certification: String,
+}
+
+impl MovieDetails {
+    pub fn year(&self) -> &str {
+        self.release_date.get(..4).unwrap_or_default()
+    }
+
+    //the imdb id, which tmdb keeps in the movie and in its external ids
+    pub fn imdb_id(&self) -> Option<&str> {
+        self.imdb_id.iter().chain(&self.external_ids.imdb_id)
+            .map(String::as_str)
+            .find(|id| !id.is_empty())
+    }
+
+    //the first certification of any release in the country
+    pub fn certification(&self, region: &str) -> Option<&str> {
+        self.release_dates.results.iter()
+            .filter(|country| country.iso_3166_1 == region)
+            .flat_map(|country| &country.release_dates)
+            .map(|release| release.certification.as_str())
+            .find(|certification| !certification.is_empty())
+    }
+}
+
+//looks the movie up by imdb id, or by tmdb id when plex has no imdb id
+pub fn get_movie_title(config: &Config, cache: &Cache, ids: &ExternalIds) -> Result<MovieTitle> {
+    let language = &config.tmdb_language;
+    if let Some(imdb_id) = &ids.imdb {
+        cache.read_through(Source::Tmdb, &format!("title/{}/{}", language, imdb_id), || fetch_movie_title(config, imdb_id))
+    } else if let Some(tmdb_id) = &ids.tmdb {
+        cache.read_through(Source::Tmdb, &format!("title/{}/tmdb/{}", language, tmdb_id),
+                           || fetch(config, &format!("/movie/{}", tmdb_id), &[("language", language)]))
+    } else {
+        Err(Error::NotFound("IMDb or TMDb Id".into()))
+    }
+}
+
+fn fetch_movie_title(config: &Config, imdb_id: &str) -> Result<MovieTitle> {
+    let mut find_results = fetch::<FindResponse>(config,
+                                             &format!("/find/{}", imdb_id),
+                                             &[
+                                                 ("language", &config.tmdb_language),
+                                                 ("external_source", "imdb_id")
+                                             ])?;
+    if find_results.movie_results.len() == 1 {
+        Ok(find_results.movie_results.remove(0).title)
+    } else {
+        Err(Error::NotFound(format!("TMDb Movie {}", imdb_id)))
+    }
+}
+
+//the tmdb id of a movie, found through its imdb id when plex has no tmdb id
+pub fn find_movie_id(config: &Config, cache: &Cache, ids: &ExternalIds) -> Result<i64> {
+    if let Some(tmdb_id) = ids.tmdb.as_ref().and_then(|id| id.parse().ok()) {
+        return Ok(tmdb_id);
+    }
+    let imdb_id = ids.imdb.as_ref().ok_or_else(|| Error::NotFound("IMDb or TMDb Id".into()))?;
+    cache.read_through(Source::Tmdb, &format!("movie_id/{}", imdb_id), || {
+        fetch::<FindResponse>(config, &format!("/find/{}", imdb_id), &[("external_source", "imdb_id")])?
+            .movie_results.first().map(|movie| movie.id)
+            .ok_or_else(|| Error::NotFound(format!("TMDb Movie {}", imdb_id)))
+    })
+}
+
+//the details of a movie plex knows by imdb or tmdb id
+pub fn get_movie_details(config: &Config, cache: &Cache, ids: &ExternalIds) -> Result<MovieDetails> {
+    get_movie_details_by_id(config, cache, find_movie_id(config, cache, ids)?)
+}
+
+pub fn get_movie_details_by_id(config: &Config, cache: &Cache, tmdb_id: i64) -> Result<MovieDetails> {
+    let language = &config.tmdb_language;
+    cache.read_through(Source::Tmdb, &format!("details/{}/{}", language, tmdb_id), || {
+        //images come in the tmdb language, along with those without text
+        let image_language = format!("{},null", language.split('-').next().unwrap_or_default());
+        fetch(config, &format!("/movie/{}", tmdb_id), &[
+            ("language", language),
+            ("append_to_response", "release_dates,credits,external_ids,images,keywords"),
+            ("include_image_language", &image_language),
+        ])
+    })
+}
+
+//movies matching the title, best match first
+pub fn search_movies(config: &Config, cache: &Cache, title: &str, year: Option<i32>) -> Result<Vec<SearchResult>> {
+    let year = year.map(|y| y.to_string()).unwrap_or_default();
+    cache.read_through(Source::Tmdb, &format!("search/{}/{}/{}", config.tmdb_language, title, year),
+                       || fetch_search_movies(config, title, &year))
+}
+
+fn fetch_search_movies(config: &Config, title: &str, year: &str) -> Result<Vec<SearchResult>> {
+    let mut query = vec![("language", config.tmdb_language.as_str()), ("query", title)];
+    if !year.is_empty() {
+        query.push(("year", year));
+    }
+    fetch::<SearchResponse>(config, "/search/movie", &query).map(|search| search.results)
+}
+
+pub fn get_imdb_id(config: &Config, cache: &Cache, tmdb_id: i64) -> Result<String> {
+    get_movie_details_by_id(config, cache, tmdb_id)?
+        .imdb_id()
+        .map(String::from)
+        .ok_or_else(|| Error::NotFound(format!("IMDb Id of TMDb Movie {}", tmdb_id)))
+}
+
+//shared by every lookup, however many threads make them
+fn limiter(config: &Config) -> &'static RateLimiter {
+    static LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+    LIMITER.get_or_init(|| RateLimiter::new(&config.tmdb_rate_limit))
+}
+
+fn fetch<T: DeserializeOwned>(config: &Config, path: &str, query: &[(&str, &str)]) -> Result<T> {
+    request::get_rate_limited_data(limiter(config),
+                                   &format!("{}{}", config.tmdb_url, path),
+                                   &[
+                                       ("Authorization", &format!("Bearer {}", config.tmdb_v4_api_key)),
+                                       ("Content-Type", "application/json;charset=utf-8"),
+                                       ("Accept", "application/json")
+                                   ],
+                                   query,
+                                   &request::client(config, "tmdb"),
+                                   request::decode)
+}
+
+//the tmdb id of a show, found through its imdb or tvdb id when plex has no tmdb id
+pub fn find_tv_id(config: &Config, cache: &Cache, ids: &ExternalIds) -> Result<i64> {
+    if let Some(tmdb_id) = ids.tmdb.as_ref().and_then(|id| id.parse().ok()) {
+        return Ok(tmdb_id);
+    }
+    let (external_id, source) = match (&ids.imdb, &ids.tvdb) {
+        (Some(imdb_id), _) => (imdb_id, "imdb_id"),
+        (None, Some(tvdb_id)) => (tvdb_id, "tvdb_id"),
+        (None, None) => return Err(Error::NotFound("IMDb, TMDb or TVDB Id".into())),
+    };
+    cache.read_through(Source::Tmdb, &format!("tv_id/{}/{}", source, external_id), || {
+        fetch::<FindResponse>(config, &format!("/find/{}", external_id), &[("external_source", source)])?
+            .tv_results.first().map(|tv| tv.id)
+            .ok_or_else(|| Error::NotFound(format!("TMDb Show {}", external_id)))
+    })
+}
+
+pub fn get_tv_details(config: &Config, cache: &Cache, tmdb_id: i64) -> Result<TvDetails> {
+    let language = &config.tmdb_language;
+    cache.read_through(Source::Tmdb, &format!("tv/{}/{}", language, tmdb_id),
+                       || fetch(config, &format!("/tv/{}", tmdb_id), &[("language", language)]))
+}
+
+pub fn get_tv_season(config: &Config, cache: &Cache, tmdb_id: i64, season: u32) -> Result<TvSeasonDetails> {
+    let language = &config.tmdb_language;
+    cache.read_through(Source::Tmdb, &format!("tv/{}/{}/season/{}", language, tmdb_id, season),
+                       || fetch(config, &format!("/tv/{}/season/{}", tmdb_id, season), &[("language", language)]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    use crate::cassette::Cassette;
+    use crate::mock::{MockResponse, MockServer};
+
+    #[test]
+    fn search_movies_by_title_and_year() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(r#"{"page":1,"total_results":1,"results":[{"id":584362,"title":"Fyre: The Greatest Party That Never Happened","release_date":"2019-01-18"}]}"#),
+        ]);
+        let config = Config::for_test(serde_json::json!({"tmdb_url": server.url, "tmdb_v4_api_key": "key"}));
+        let results = search_movies(&config, &Cache::for_test(), "Fyre the Greatest Party That Never Happened", Some(2019)).unwrap();
+        assert_eq!(results[0].id, 584362);
+        assert_eq!(results[0].year(), "2019");
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/search/movie?language=en-US&query=Fyre%20the%20Greatest%20Party%20That%20Never%20Happened&year=2019");
+        assert_eq!(request.header("Authorization"), Some("Bearer key"));
+    }
+
+    #[test]
+    fn movie_details_by_imdb_id() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(r#"{"movie_results":[{"id":550,"title":"Fight Club","original_title":"Fight Club","original_language":"en"}]}"#),
+            MockResponse::ok(r#"{"id":550,"imdb_id":"tt0137523","title":"Fight Club","original_title":"Fight Club",
+                "original_language":"en","release_date":"1999-10-15","runtime":139,"tagline":"Mischief. Mayhem. Soap.",
+                "genres":[{"id":18,"name":"Drama"}],"belongs_to_collection":null,
+                "release_dates":{"results":[{"iso_3166_1":"US","release_dates":[{"certification":"R"}]}]},
+                "credits":{"cast":[{"id":819,"name":"Edward Norton","character":"The Narrator","order":0}],
+                           "crew":[{"id":7467,"name":"David Fincher","job":"Director","department":"Directing"}]},
+                "external_ids":{"imdb_id":"tt0137523","wikidata_id":"Q190050","facebook_id":null},
+                "images":{"backdrops":[],"posters":[{"file_path":"/pB8BM7pdSp6B6Ih7QZ4DrQ3PmJK.jpg","iso_639_1":"en","width":2000,"height":3000,"vote_average":5.6}],"logos":[]},
+                "keywords":{"keywords":[{"id":825,"name":"support group"}]}}"#),
+        ]);
+        let config = Config::for_test(serde_json::json!({"tmdb_url": server.url}));
+        let cache = Cache::for_test();
+        let ids = ExternalIds { imdb: Some("tt0137523".into()), ..Default::default() };
+        let details = get_movie_details(&config, &cache, &ids).unwrap();
+        assert_eq!((details.runtime, details.certification("US")), (Some(139), Some("R")));
+        assert_eq!(details.credits.crew[0].job, "Director");
+        assert_eq!(details.external_ids.wikidata_id.as_deref(), Some("Q190050"));
+        assert_eq!(details.images.posters[0].width, 2000);
+        assert_eq!(details.keywords.keywords[0].name, "support group");
+        //the tmdb id reaches the same cached details
+        assert_eq!(get_imdb_id(&config, &cache, 550).unwrap(), "tt0137523");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].path, "/movie/550?language=en-US&append_to_response=release_dates,credits,external_ids,images,keywords&include_image_language=en,null");
+    }
+
+    #[test]
+    fn movie_title_from_cassette() {
+        let mut config = Config::for_test(serde_json::json!({"tmdb_v4_api_key": "key"}));
+        config.cassette = Some(Cassette::replay(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/tmdb_find.json")),
+                                                config.secrets()).unwrap());
+        let ids = ExternalIds { imdb: Some("tt0137523".into()), ..Default::default() };
+        assert_eq!(get_movie_title(&config, &Cache::for_test(), &ids).unwrap(), MovieTitle {
+            title: "Fight Club".into(),
+            original_title: "Fight Club".into(),
+            original_language: "en".into(),
+        });
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::plex::{Episode, Show};
+use crate::tmdb::{TvEpisode, TvSeasonDetails};
+
+pub struct MissingEpisode {
+    pub season: u32,
+    pub episode: u32,
+    pub name: String,
+    pub air_date: String,
+}
+
+//today as "YYYY-MM-DD", comparable with tmdb air dates
+pub fn today() -> String {
+    date(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86_400).unwrap_or(0) as i64)
+}
+
+//days since 1970-01-01 to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+fn date(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+//whether a plex episode is the tmdb episode with the same number, judged by both air dates
+pub fn same_air_date(episode: &Episode, tmdb_episode: &TvEpisode) -> bool {
+    match (&episode.aired, &tmdb_episode.air_date) {
+        (Some(aired), Some(air_date)) => !aired.is_empty() && aired == air_date,
+        _ => false,
+    }
+}
+
+//episodes of the season that aired by today but are not in plex
+pub fn missing_episodes(show: &Show, season: u32, tmdb_season: &TvSeasonDetails, today: &str) -> Vec<MissingEpisode> {
+    let in_plex = show.seasons.get(&season);
+    tmdb_season.episodes.iter()
+        .filter(|e| in_plex.map(|s| !s.episodes.contains_key(&e.episode_number)).unwrap_or(true))
+        .filter_map(|e| e.air_date.as_ref()
+            .filter(|air_date| !air_date.is_empty() && air_date.as_str() <= today)
+            .map(|air_date| MissingEpisode {
+                season,
+                episode: e.episode_number,
+                name: e.name.clone(),
+                air_date: air_date.clone(),
+            }))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use crate::plex::Season;
+
+    #[test]
+    fn aired_episodes_missing_from_plex() {
+        let mut season = Season::default();
+        season.episodes.insert(1, Episode {
+            plex_key: "101".into(),
+            title: "Pilot".into(),
+            media: vec![],
+            aired: Some("2008-01-20".into()),
+        });
+        let mut seasons = BTreeMap::new();
+        seasons.insert(1, season);
+        let show = Show {
+            section: "2".into(),
+            plex_key: "100".into(),
+            title: "Breaking Bad".into(),
+            year: Some(2008),
+            title_sort: None,
+            ids: Default::default(),
+            seasons,
+        };
+        let episode = |number: u32, air_date: Option<&str>| TvEpisode {
+            episode_number: number,
+            name: format!("Episode {}", number),
+            air_date: air_date.map(String::from),
+        };
+        let tmdb_season = TvSeasonDetails {
+            episodes: vec![
+                episode(1, Some("2008-01-20")),
+                episode(2, Some("2008-01-27")),
+                episode(3, Some("2030-01-01")),
+                episode(4, None),
+            ],
+        };
+
+        let missing = missing_episodes(&show, 1, &tmdb_season, "2020-06-01");
+        assert_eq!(missing.len(), 1);
+        assert_eq!((missing[0].season, missing[0].episode), (1, 2));
+        assert_eq!(missing_episodes(&show, 2, &tmdb_season, "2020-06-01").len(), 2);
+        assert!(same_air_date(&show.seasons[&1].episodes[&1], &tmdb_season.episodes[0]));
+        assert!(!same_air_date(&show.seasons[&1].episodes[&1], &tmdb_season.episodes[1]));
+        assert!(!same_air_date(&show.seasons[&1].episodes[&1], &tmdb_season.episodes[3]));
+    }
+
+    #[test]
+    fn days_to_date() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(11_016), "2000-02-29");
+        assert_eq!(date(20_088), "2024-12-31");
+    }
+}
@@ -0,0 +1,93 @@
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::request;
+
+#[derive(Deserialize)]
+struct ListMoviesResponse {
+    data: ListMoviesData,
+}
+
+#[derive(Deserialize)]
+struct ListMoviesData {
+    #[serde(default)]
+    movies: Vec<YtsMovie>,
+}
+
+#[derive(Deserialize)]
+struct YtsMovie {
+    imdb_code: String,
+    title: String,
+    year: i32,
+    #[serde(default)]
+    torrents: Vec<YtsTorrent>,
+}
+
+#[derive(Deserialize)]
+struct YtsTorrent {
+    hash: String,
+    quality: String,
+    video_codec: Option<String>,
+    size_bytes: u64,
+}
+
+pub struct Torrent {
+    pub title: String,
+    pub year: i32,
+    pub quality: String,
+    pub video_codec: Option<String>,
+    pub size_bytes: u64,
+    pub hash: String,
+}
+
+impl Torrent {
+    pub fn magnet(&self) -> String {
+        format!("magnet:?xt=urn:btih:{}&dn={}", self.hash, self.title.replace(' ', "+"))
+    }
+}
+
+//torrents for the movie, the preferred quality first
+pub fn find_torrents(config: &Config, imdb_id: &str) -> Result<Vec<Torrent>> {
+    request::get_response_data(&format!("{}/list_movies.json", config.yts_url.trim_end_matches('/')),
+                               &[("Accept", "application/json")],
+                               &[("query_term", imdb_id)],
+                               &request::client(config, "yts"),
+                               |response| -> Result<Vec<Torrent>> {
+                                   let list: ListMoviesResponse = request::decode(response)?;
+                                   let mut torrents: Vec<Torrent> = list.data.movies.into_iter()
+                                       .filter(|m| m.imdb_code == imdb_id)
+                                       .flat_map(|m| {
+                                           let (title, year) = (m.title, m.year);
+                                           m.torrents.into_iter().map(move |t| Torrent {
+                                               title: title.clone(),
+                                               year,
+                                               quality: t.quality,
+                                               video_codec: t.video_codec,
+                                               size_bytes: t.size_bytes,
+                                               hash: t.hash.to_lowercase(),
+                                           })
+                                       })
+                                       .collect();
+                                   torrents.sort_by_key(|t| t.quality != config.torrent_quality);
+                                   Ok(torrents)
+                               })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    #[test]
+    fn find_torrents_prefers_quality() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(r#"{"status":"ok","data":{"movie_count":1,"movies":[{"imdb_code":"tt0137523","title":"Fight Club","year":1999,"torrents":[{"hash":"AAAA","quality":"720p","size_bytes":1000},{"hash":"BBBB","quality":"1080p","size_bytes":2000}]}]}}"#),
+        ]);
+        let config = Config::for_test(serde_json::json!({"yts_url": server.url, "torrent_quality": "1080p"}));
+        let torrents = find_torrents(&config, "tt0137523").unwrap();
+        assert_eq!(torrents[0].hash, "bbbb");
+        assert_eq!(torrents[0].magnet(), "magnet:?xt=urn:btih:bbbb&dn=Fight+Club");
+        assert_eq!(server.requests()[0].path, "/list_movies.json?query_term=tt0137523");
+    }
+}